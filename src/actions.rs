@@ -0,0 +1,526 @@
+use crate::policy::Policy;
+use crate::scan::{device_and_inode, file_error, files_equal};
+use crate::{Duplicate, Event, FileInfo, Observer, RedundantDir, ScanResult};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What happens to files of redundant directories.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Removal {
+    DryRun,
+    Trash,
+    Unlink,
+}
+
+fn remove_file(path: &str, removal: Removal) -> io::Result<()> {
+    match removal {
+        Removal::DryRun => Ok(()),
+        Removal::Trash => trash::delete(path).map_err(|e| io::Error::other(e.to_string())),
+        Removal::Unlink => fs::remove_file(path),
+    }
+}
+
+/// What an action did, or would do in a dry run.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Summary {
+    pub files: usize,
+    /// Bytes of removed files, or bytes reclaimed by links.
+    pub size: usize,
+    /// Redundant directories whose files were removed, 0 for links.
+    pub dirs: usize,
+}
+
+/// Files of `dir` and its subdirectories paired with a copy of each of them
+/// outside of `dir` and of the `removed` directories, or an error if some file
+/// has no such copy or is not byte-identical to it.
+pub(crate) fn verified_copies<'a>(
+    dir: &str,
+    result: &'a ScanResult,
+    removed: &HashSet<String>,
+) -> Result<Vec<(&'a FileInfo, &'a FileInfo)>, String> {
+    let in_tree = |file: &FileInfo, dir: &str| Path::new(&file.dir).starts_with(dir);
+    let hashes: HashSet<u64> = result
+        .dir_hashes
+        .iter()
+        .filter(|(file_dir, _)| Path::new(file_dir).starts_with(dir))
+        .flat_map(|(_, hashes)| hashes.iter().copied())
+        .collect();
+    let mut copies = Vec::new();
+    for hash in hashes {
+        let group = &result.groups[hash as usize];
+        let kept: Vec<&FileInfo> = group
+            .iter()
+            .filter(|file| {
+                !in_tree(file, dir) && !removed.iter().any(|removed| in_tree(file, removed))
+            })
+            .collect();
+        for file in group.iter().filter(|file| in_tree(file, dir)) {
+            let copy = kept
+                .iter()
+                .find(|copy| matches!(files_equal(copy, file), Ok(true)))
+                .ok_or_else(|| format!("{} has no verified copy", file.path))?;
+            copies.push((file, *copy));
+        }
+    }
+    copies.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+    Ok(copies)
+}
+
+/// Removes `dir` and those of its subdirectories with removed `files` which
+/// became empty, deepest first. Returns whether `dir` itself was removed.
+fn remove_empty_dirs(dir: &str, files: &[(&FileInfo, &FileInfo)]) -> bool {
+    let mut dirs: BTreeSet<&Path> = BTreeSet::new();
+    for (file, _) in files.iter() {
+        dirs.extend(
+            Path::new(&file.dir)
+                .ancestors()
+                .take_while(|path| path.starts_with(dir)),
+        );
+    }
+    dirs.insert(Path::new(dir));
+    let mut dirs: Vec<&Path> = dirs.into_iter().collect();
+    dirs.sort_by_key(|path| Reverse(path.components().count()));
+    // Fails for directories with files which were not scanned.
+    let mut removed = false;
+    for path in dirs {
+        removed = fs::remove_dir(path).is_ok();
+    }
+    removed
+}
+
+/// Removes files of redundant directories, see `delete_dirs`.
+pub fn delete_redundant(
+    redundant_dirs: &[RedundantDir],
+    result: &ScanResult,
+    removal: Removal,
+    observer: &dyn Observer,
+) -> Summary {
+    let dirs: Vec<&str> = redundant_dirs.iter().map(|dir| dir.dir.as_str()).collect();
+    delete_dirs(&dirs, result, removal, observer)
+}
+
+/// Removes files of `dirs` and their subdirectories after comparing each of
+/// them byte by byte with its copy, then removes the directories which became
+/// empty. Files which were not scanned stay in place.
+/// Directories are processed in the given order, and a directory is skipped
+/// when some of its files have copies only in directories removed before it,
+/// so both sides of a pair of duplicates are never removed. Every removed or
+/// skipped file and directory and every file which can't be removed is
+/// reported to `observer`.
+pub fn delete_dirs(
+    dirs: &[&str],
+    result: &ScanResult,
+    removal: Removal,
+    observer: &dyn Observer,
+) -> Summary {
+    let mut removed: HashSet<String> = HashSet::new();
+    let mut summary = Summary::default();
+
+    for dir in dirs.iter() {
+        let copies = match verified_copies(dir, result, &removed) {
+            Ok(copies) => copies,
+            Err(reason) => {
+                observer.event(&Event::Skipped {
+                    path: Path::new(dir),
+                    reason: &reason,
+                });
+                continue;
+            }
+        };
+        removed.insert(dir.to_string());
+
+        for (file, copy) in copies.iter() {
+            match remove_file(&file.path, removal) {
+                Ok(()) => {
+                    observer.event(&Event::FileRemoved {
+                        path: Path::new(&file.path),
+                        copy: Path::new(&copy.path),
+                        dry_run: removal == Removal::DryRun,
+                    });
+                    summary.files += 1;
+                    summary.size += file.size;
+                }
+                Err(e) => file_error(observer, &file.path, e),
+            }
+        }
+        if removal != Removal::DryRun {
+            observer.event(&Event::DirCleared {
+                path: Path::new(dir),
+                removed: remove_empty_dirs(dir, &copies),
+            });
+        }
+    }
+    summary.dirs = removed.len();
+    summary
+}
+
+/// How duplicate files are replaced by `link_duplicates`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LinkKind {
+    Hardlink,
+    Reflink,
+}
+
+impl LinkKind {
+    pub const VARIANTS: &'static [&'static str] = &["hardlink", "reflink"];
+}
+
+impl FromStr for LinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LinkKind, String> {
+        match s {
+            "hardlink" => Ok(LinkKind::Hardlink),
+            "reflink" => Ok(LinkKind::Reflink),
+            _ => Err(format!("unknown link kind: {}", s)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn links_number(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::nlink(metadata)
+}
+
+#[cfg(not(unix))]
+fn links_number(_metadata: &Metadata) -> u64 {
+    1
+}
+
+#[cfg(target_os = "linux")]
+fn clone_file(source: &File, target: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: both descriptors are open for the duration of the call.
+    let result = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clone_file(_source: &File, _target: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are supported only on Linux",
+    ))
+}
+
+/// A hidden file next to `path`, used to replace `path` atomically.
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.dirdups-tmp", name))
+}
+
+/// Replaces `target` with a link to `source`. A reflink keeps permissions and
+/// modification time of `target`, a hardlink shares them with `source`.
+fn replace_with_link(source: &str, target: &str, kind: LinkKind) -> io::Result<()> {
+    let tmp_path = temporary_path(Path::new(target));
+    let result = match kind {
+        LinkKind::Hardlink => fs::hard_link(source, &tmp_path),
+        LinkKind::Reflink => {
+            let target_metadata = fs::metadata(target)?;
+            let source_file = File::open(source)?;
+            let tmp_file = File::create(&tmp_path)?;
+            clone_file(&source_file, &tmp_file)
+                .and_then(|_| tmp_file.set_permissions(target_metadata.permissions()))
+                .and_then(|_| tmp_file.set_modified(target_metadata.modified()?))
+        }
+    };
+    match result.and_then(|_| fs::rename(&tmp_path, target)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Replaces duplicate files of every pair in `duplicates` with links to one of
+/// their copies, so both directory trees stay in place but share the disk
+/// space. Files are compared byte by byte first, and files on different
+/// filesystems and symlinks are never linked. Files of the directory kept by
+/// the policy are linked to, and files in protected directories are left
+/// untouched. Reports how many bytes were reclaimed, counting only files which
+/// had no other hardlinks, since blocks of the others stay allocated. Blocks a
+/// reflink target already shared with its source can't be told apart, so for
+/// reflinks the number is an upper bound. Every linked or skipped file and
+/// every file which can't be read or linked is reported to `observer`.
+pub fn link_duplicates(
+    duplicates: &[Duplicate],
+    result: &ScanResult,
+    policy: &Policy,
+    kind: LinkKind,
+    dry_run: bool,
+    observer: &dyn Observer,
+) -> Summary {
+    let files_info: HashMap<&String, &FileInfo> =
+        result.files().map(|file| (&file.path, file)).collect();
+    let mut linked: HashSet<&String> = HashSet::new();
+    let mut summary = Summary::default();
+
+    for duplicate in duplicates.iter() {
+        // Files of the directory which the keep policy prefers are the sources.
+        let keep_dir2 = duplicate
+            .decision
+            .as_ref()
+            .is_some_and(|decision| decision.keep == duplicate.dir2);
+        for shared in duplicate.shared_files.iter() {
+            let mut files = if keep_dir2 {
+                shared.dir2_files.iter().chain(shared.dir1_files.iter())
+            } else {
+                shared.dir1_files.iter().chain(shared.dir2_files.iter())
+            };
+            let source = match files.next() {
+                Some(source) => source,
+                None => continue,
+            };
+            // Symlinks are not followed, a link to a symlink would be a link
+            // to the symlink itself.
+            let source_metadata = match fs::symlink_metadata(source) {
+                Ok(metadata) if metadata.is_symlink() => continue,
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    file_error(observer, source, e);
+                    continue;
+                }
+            };
+            let (source_device, source_inode) = device_and_inode(&source_metadata);
+
+            for target in files {
+                let protected = Path::new(target)
+                    .parent()
+                    .is_some_and(|dir| policy.is_protected(&dir.to_string_lossy()));
+                if protected || !linked.insert(target) {
+                    continue;
+                }
+                let target_metadata = match fs::symlink_metadata(target) {
+                    Ok(metadata) if metadata.is_symlink() => continue,
+                    Ok(metadata) => metadata,
+                    Err(e) => {
+                        file_error(observer, target, e);
+                        continue;
+                    }
+                };
+                let (target_device, target_inode) = device_and_inode(&target_metadata);
+                if target_device != source_device {
+                    observer.event(&Event::Skipped {
+                        path: Path::new(target),
+                        reason: &format!("on another filesystem than {}", source),
+                    });
+                    continue;
+                }
+                if kind == LinkKind::Hardlink && target_inode == source_inode {
+                    continue;
+                }
+                match files_equal(files_info[source], files_info[target]) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        observer.event(&Event::Skipped {
+                            path: Path::new(target),
+                            reason: &format!("differs from {}", source),
+                        });
+                        continue;
+                    }
+                    Err((failed, e)) => {
+                        file_error(observer, &failed.path, e);
+                        continue;
+                    }
+                }
+
+                if !dry_run {
+                    if let Err(e) = replace_with_link(source, target, kind) {
+                        file_error(observer, target, e);
+                        continue;
+                    }
+                }
+                observer.event(&Event::FileLinked {
+                    path: Path::new(target),
+                    source: Path::new(source),
+                    dry_run,
+                });
+                summary.files += 1;
+                if links_number(&target_metadata) == 1 {
+                    summary.size += files_info[target].size;
+                }
+            }
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::Silent;
+    use crate::policy::Rule;
+    use crate::Scanner;
+
+    /// Creates `files` under a new temporary directory and scans it.
+    fn scan(name: &str, files: &[(&str, &str)]) -> (PathBuf, ScanResult) {
+        let root = std::env::temp_dir().join(format!("dirdups-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in files.iter() {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let result = Scanner::new([root.to_string_lossy()]).scan().unwrap();
+        (root, result)
+    }
+
+    fn path(root: &Path, dir: &str) -> String {
+        String::from(root.join(dir).to_string_lossy())
+    }
+
+    #[test]
+    fn identical_dirs_are_not_both_deleted() {
+        let (root, result) = scan("identical", &[("a/f", "same"), ("b/f", "same")]);
+        let dirs = [path(&root, "a"), path(&root, "b")];
+        let dirs: Vec<&str> = dirs.iter().map(String::as_str).collect();
+        let summary = delete_dirs(&dirs, &result, Removal::Unlink, &Silent);
+        assert_eq!((summary.files, summary.dirs), (1, 1));
+        assert!(root.join("a/f").exists() != root.join("b/f").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn copies_in_removed_dirs_do_not_count() {
+        let (root, result) = scan("removed", &[("a/f", "same"), ("b/f", "same")]);
+        let removed = HashSet::from([path(&root, "b")]);
+        assert!(verified_copies(&path(&root, "a"), &result, &removed).is_err());
+        assert!(verified_copies(&path(&root, "a"), &result, &HashSet::new()).is_ok());
+        fs::remove_dir_all(root).unwrap();
+
+        // Removing `b` removes its subdirectories with it.
+        let (root, result) = scan("removed-nested", &[("a/f", "same"), ("b/sub/f", "same")]);
+        let removed = HashSet::from([path(&root, "b")]);
+        assert!(verified_copies(&path(&root, "a"), &result, &removed).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn copies_inside_the_dir_do_not_count() {
+        let (root, result) = scan("nested", &[("a/f", "same"), ("a/sub/f", "same")]);
+        assert!(verified_copies(&path(&root, "a"), &result, &HashSet::new()).is_err());
+
+        let summary = delete_dirs(&[&path(&root, "a")], &result, Removal::Unlink, &Silent);
+        assert_eq!((summary.files, summary.dirs), (0, 0));
+        assert!(root.join("a/f").exists() && root.join("a/sub/f").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn dry_run_keeps_files() {
+        let (root, result) = scan("dry-run", &[("a/f", "same"), ("b/f", "same")]);
+        let summary = delete_dirs(&[&path(&root, "a")], &result, Removal::DryRun, &Silent);
+        assert_eq!((summary.files, summary.size, summary.dirs), (1, 4, 1));
+        assert!(root.join("a/f").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    fn inode(path: &Path) -> (u64, u64) {
+        device_and_inode(&fs::symlink_metadata(path).unwrap())
+    }
+
+    fn link(result: &ScanResult, rules: Vec<Rule>) -> Summary {
+        let policy = Policy::new(rules, result);
+        let duplicates = result.duplicates(1);
+        link_duplicates(
+            &duplicates,
+            result,
+            &policy,
+            LinkKind::Hardlink,
+            false,
+            &Silent,
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlinks_replace_duplicates() {
+        let (root, result) = scan("hardlink", &[("a/f", "same"), ("b/f", "same")]);
+        let summary = link(&result, Vec::new());
+        assert_eq!((summary.files, summary.size), (1, 4));
+        assert_eq!(inode(&root.join("a/f")), inode(&root.join("b/f")));
+        assert_eq!(fs::read_to_string(root.join("b/f")).unwrap(), "same");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_with_other_hardlinks_reclaim_nothing() {
+        let (root, _) = scan("hardlink-nlink", &[("a/f", "same"), ("b/f", "same")]);
+        // A hardlink outside of the scanned directory keeps the blocks of b/f.
+        let outside = root.with_extension("outside");
+        let _ = fs::remove_file(&outside);
+        fs::hard_link(root.join("b/f"), &outside).unwrap();
+        let result = Scanner::new([root.to_string_lossy()]).scan().unwrap();
+
+        let summary = link(&result, Vec::new());
+        assert_eq!((summary.files, summary.size), (1, 0));
+        assert_eq!(inode(&root.join("a/f")), inode(&root.join("b/f")));
+        fs::remove_file(outside).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn protected_dirs_are_not_linked() {
+        let (root, result) = scan("hardlink-protected", &[("a/f", "same"), ("b/f", "same")]);
+        let protect = Rule::Protect(format!("{}/", path(&root, "b")));
+        let summary = link(&result, vec![protect]);
+        assert_eq!(summary.files, 0);
+        assert_ne!(inode(&root.join("a/f")), inode(&root.join("b/f")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_not_linked() {
+        let root = std::env::temp_dir().join(format!("dirdups-symlinks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["a", "b"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            std::os::unix::fs::symlink("/nonexistent/target", root.join(dir).join("l")).unwrap();
+        }
+        let result = Scanner::new([root.to_string_lossy()])
+            .symlinks_as_entries(true)
+            .scan()
+            .unwrap();
+        assert_eq!(result.duplicates(1).len(), 1);
+
+        let summary = link(&result, Vec::new());
+        assert_eq!(summary.files, 0);
+        assert!(fs::symlink_metadata(root.join("b/l")).unwrap().is_symlink());
+        assert_ne!(inode(&root.join("a/l")), inode(&root.join("b/l")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_on_other_filesystems_are_not_linked() {
+        let (root, _) = scan("hardlink-devices", &[("a/f", "same")]);
+        // Runs only where /dev/shm is another filesystem than the temporary directory.
+        let other = Path::new("/dev/shm").join(format!("dirdups-{}", std::process::id()));
+        if fs::create_dir_all(other.join("b")).is_err() || inode(&other).0 == inode(&root).0 {
+            let _ = fs::remove_dir_all(&other);
+            fs::remove_dir_all(root).unwrap();
+            return;
+        }
+        fs::write(other.join("b/f"), "same").unwrap();
+        let result = Scanner::new([root.to_string_lossy(), other.to_string_lossy()])
+            .scan()
+            .unwrap();
+
+        let summary = link(&result, Vec::new());
+        assert_eq!(summary.files, 0);
+        assert_eq!(fs::read_to_string(other.join("b/f")).unwrap(), "same");
+        fs::remove_dir_all(other).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
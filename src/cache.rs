@@ -0,0 +1,262 @@
+use crate::hash::{Digest, HashAlgorithm};
+use crate::scan::FileInfo;
+use crate::walk::FoundFile;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+// Version 1 keyed entries by paths as they were typed.
+const HEADER: &str = "dirdups-cache 2";
+
+struct CacheEntry {
+    size: usize,
+    mtime: u128,
+    inode: u64,
+    algorithm: HashAlgorithm,
+    head: usize,
+    head_hash: Option<Digest>,
+    full_hash: Option<Digest>,
+}
+
+/// Checksums of files computed by previous runs, keyed by paths with symlinks
+/// resolved, so a file is found however it was reached. An entry is only used while
+/// the size, modification time and inode of the file and the hash algorithm
+/// stay the same; the head checksum also requires the same `--head` value.
+pub struct Cache {
+    path: PathBuf,
+    entries: HashMap<String, CacheEntry>,
+}
+
+fn parse_hash(s: &str) -> Option<Option<Digest>> {
+    match s {
+        "-" => Some(None),
+        _ => s.parse().ok().map(Some),
+    }
+}
+
+fn format_hash(hash: &Option<Digest>) -> String {
+    match hash {
+        Some(hash) => hash.to_string(),
+        None => String::from("-"),
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, CacheEntry)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 8 {
+        return None;
+    }
+    let entry = CacheEntry {
+        size: fields[1].parse().ok()?,
+        mtime: fields[2].parse().ok()?,
+        inode: fields[3].parse().ok()?,
+        algorithm: fields[4].parse().ok()?,
+        head: fields[5].parse().ok()?,
+        head_hash: parse_hash(fields[6])?,
+        full_hash: parse_hash(fields[7])?,
+    };
+    Some((String::from(fields[0]), entry))
+}
+
+impl Cache {
+    /// `$XDG_CACHE_HOME/dirdups/fingerprints`, or `~/.cache/dirdups/fingerprints`.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_home = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        Some(cache_home.join("dirdups").join("fingerprints"))
+    }
+
+    /// Loads the cache, a missing or unreadable cache file gives an empty cache.
+    pub fn load(path: PathBuf) -> Cache {
+        let mut entries = HashMap::new();
+        if let Ok(f) = File::open(&path) {
+            let mut lines = BufReader::new(f).lines().map_while(Result::ok);
+            if lines.next().as_deref() == Some(HEADER) {
+                entries.extend(lines.filter_map(|line| parse_line(&line)));
+            }
+        }
+        Cache { path, entries }
+    }
+
+    /// Fills in checksums of `file` which are still valid.
+    pub fn lookup(&self, file: &mut FileInfo, head: usize, algorithm: HashAlgorithm) {
+        let entry = match self.entries.get(&file.real_path) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if entry.size != file.size
+            || entry.mtime != file.mtime
+            || entry.inode != file.inode
+            || entry.algorithm != algorithm
+        {
+            return;
+        }
+        if entry.head == head {
+            file.head_hash = entry.head_hash;
+        }
+        file.full_hash = entry.full_hash;
+    }
+
+    pub fn update(&mut self, file: &FileInfo, head: usize, algorithm: HashAlgorithm) {
+        // Nothing to remember, or a path which would break the line format.
+        if (file.head_hash.is_none() && file.full_hash.is_none())
+            || file.real_path.contains(['\t', '\n'])
+        {
+            self.entries.remove(&file.real_path);
+            return;
+        }
+        self.entries.insert(
+            file.real_path.clone(),
+            CacheEntry {
+                size: file.size,
+                mtime: file.mtime,
+                inode: file.inode,
+                algorithm,
+                head,
+                head_hash: file.head_hash,
+                full_hash: file.full_hash,
+            },
+        );
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Removes entries of files below `directories` which are not in `files`
+    /// anymore. `directories` must have their symlinks resolved too.
+    pub fn retain_seen(&mut self, directories: &[PathBuf], files: &[FoundFile]) {
+        let seen: HashSet<&String> = files.iter().map(|file| &file.real_path).collect();
+        self.entries.retain(|path, _| {
+            seen.contains(path)
+                || !directories
+                    .iter()
+                    .any(|dir| Path::new(path).starts_with(dir))
+        });
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first, so an interrupted run can't corrupt the cache.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut f = BufWriter::new(File::create(&tmp_path)?);
+        writeln!(f, "{}", HEADER)?;
+        for (path, entry) in self.entries.iter() {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                path,
+                entry.size,
+                entry.mtime,
+                entry.inode,
+                entry.algorithm,
+                entry.head,
+                format_hash(&entry.head_hash),
+                format_hash(&entry.full_hash)
+            )?;
+        }
+        f.into_inner()?.sync_all()?;
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(real_path: &str) -> FileInfo {
+        FileInfo {
+            path: String::from(real_path),
+            dir: String::new(),
+            size: 100,
+            mtime: 1000,
+            device: 1,
+            inode: 42,
+            is_symlink: false,
+            real_path: String::from(real_path),
+            head_hash: None,
+            full_hash: None,
+            content: 0,
+        }
+    }
+
+    fn digest(byte: &str) -> Option<Digest> {
+        Some(byte.repeat(32).parse().unwrap())
+    }
+
+    /// A cache saved with one entry for `/data/f` and loaded back.
+    fn saved_cache(name: &str) -> Cache {
+        let path = env::temp_dir().join(format!("dirdups-{}-{}", name, std::process::id()));
+        let mut cache = Cache::load(path.clone());
+        let mut f = file("/data/f");
+        f.head_hash = digest("01");
+        f.full_hash = digest("02");
+        cache.update(&f, 1024, HashAlgorithm::Xxh3);
+        cache.save().unwrap();
+        let cache = Cache::load(path.clone());
+        fs::remove_file(path).unwrap();
+        cache
+    }
+
+    #[test]
+    fn saved_checksums_are_found() {
+        let cache = saved_cache("cache-saved");
+        let mut f = file("/data/f");
+        cache.lookup(&mut f, 1024, HashAlgorithm::Xxh3);
+        assert_eq!((f.head_hash, f.full_hash), (digest("01"), digest("02")));
+    }
+
+    #[test]
+    fn changed_files_are_not_found() {
+        let cache = saved_cache("cache-changed");
+        let changes: [fn(&mut FileInfo); 3] = [|f| f.size += 1, |f| f.mtime += 1, |f| f.inode += 1];
+        for change in changes {
+            let mut f = file("/data/f");
+            change(&mut f);
+            cache.lookup(&mut f, 1024, HashAlgorithm::Xxh3);
+            assert_eq!((f.head_hash, f.full_hash), (None, None));
+        }
+        let mut f = file("/data/f");
+        cache.lookup(&mut f, 1024, HashAlgorithm::Sha256);
+        assert_eq!((f.head_hash, f.full_hash), (None, None));
+        // The full checksum doesn't depend on the head size.
+        let mut f = file("/data/f");
+        cache.lookup(&mut f, 4096, HashAlgorithm::Xxh3);
+        assert_eq!((f.head_hash, f.full_hash), (None, digest("02")));
+    }
+
+    #[test]
+    fn only_missing_files_of_scanned_dirs_are_removed() {
+        let mut cache = Cache::load(PathBuf::from("/nonexistent/cache"));
+        for path in ["/data/seen", "/data/sub/gone", "/other/f"] {
+            let mut f = file(path);
+            f.full_hash = digest("03");
+            cache.update(&f, 0, HashAlgorithm::Xxh3);
+        }
+        let seen = FoundFile {
+            path: String::from("seen"),
+            real_path: String::from("/data/seen"),
+        };
+        cache.retain_seen(&[PathBuf::from("/data")], &[seen]);
+        let mut paths: Vec<&String> = cache.entries.keys().collect();
+        paths.sort();
+        assert_eq!(paths, ["/data/seen", "/other/f"]);
+    }
+
+    #[test]
+    fn caches_of_older_versions_are_ignored() {
+        let path = env::temp_dir().join(format!("dirdups-cache-v1-{}", std::process::id()));
+        let line = "/data/f\t100\t1000\t42\txxh3\t0\t-\t-";
+        fs::write(&path, format!("dirdups-cache 1\n{}\n", line)).unwrap();
+        assert!(Cache::load(path.clone()).entries.is_empty());
+        fs::write(&path, format!("{}\n{}\n", HEADER, line)).unwrap();
+        assert_eq!(Cache::load(path.clone()).entries.len(), 1);
+        fs::remove_file(path).unwrap();
+    }
+}
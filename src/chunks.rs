@@ -0,0 +1,156 @@
+use crate::observer::{Event, Observer, Stage};
+use crate::scan::{file_error, open_content, FileInfo, ScanResult};
+use fastcdc::v2020::{StreamCDC, AVERAGE_MAX, AVERAGE_MIN};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// A chunk of a file: checksum of its contents and its length.
+pub(crate) type Chunk = (u64, usize);
+
+/// Checks that `avg_size` is a chunk size FastCDC accepts.
+pub(crate) fn check_chunk_size(avg_size: usize) -> Result<(), String> {
+    if (AVERAGE_MIN as usize..=AVERAGE_MAX as usize).contains(&avg_size) {
+        Ok(())
+    } else {
+        Err(format!(
+            "chunk size must be between {} and {} bytes",
+            AVERAGE_MIN, AVERAGE_MAX
+        ))
+    }
+}
+
+/// Splits the contents of `file` at boundaries found by FastCDC, so an edit
+/// changes only the chunks around it and the rest of the file still matches.
+fn get_chunks(file: &FileInfo, avg_size: usize) -> io::Result<Vec<Chunk>> {
+    let avg_size = avg_size as u32;
+    let chunker = StreamCDC::new(open_content(file)?, avg_size / 4, avg_size, avg_size * 4);
+    let mut chunks = Vec::new();
+    for chunk in chunker {
+        let chunk = chunk?;
+        chunks.push((xxh3_64(&chunk.data), chunk.length));
+    }
+    Ok(chunks)
+}
+
+/// Chunks of every group of identical files, read from the first file of the
+/// group which can be read. Groups none of whose files can be read get no chunks.
+pub(crate) fn chunk_groups(
+    groups: &[Vec<FileInfo>],
+    avg_size: usize,
+    observer: &dyn Observer,
+) -> Vec<Vec<Chunk>> {
+    observer.event(&Event::StageStarted {
+        stage: Stage::Chunk,
+        files: groups.len(),
+    });
+    let chunks = groups
+        .par_iter()
+        .map(|group| {
+            for file in group.iter() {
+                let chunks = get_chunks(file, avg_size);
+                observer.event(&Event::FileHashed {
+                    stage: Stage::Chunk,
+                    path: Path::new(&file.path),
+                });
+                match chunks {
+                    Ok(chunks) => return chunks,
+                    Err(e) => file_error(observer, &file.path, e),
+                }
+            }
+            Vec::new()
+        })
+        .collect();
+    observer.event(&Event::StageFinished {
+        stage: Stage::Chunk,
+    });
+    chunks
+}
+
+/// Two directories whose files have chunks in common, though the files
+/// themselves may differ, e.g. re-tagged songs or re-saved documents.
+#[derive(Serialize)]
+pub struct NearDuplicate {
+    pub dir1: String,
+    pub dir2: String,
+    pub dir1_files_number: usize,
+    pub dir2_files_number: usize,
+    /// Size of distinct chunks of the directory.
+    pub dir1_size: usize,
+    pub dir2_size: usize,
+    /// Size of distinct chunks found in both directories, an estimate of how
+    /// much of their contents is shared.
+    pub shared_size: usize,
+    /// Shared size divided by the size of distinct chunks of both directories.
+    pub jaccard: f64,
+    /// Shared size divided by the size of the smaller directory.
+    pub containment: f64,
+}
+
+/// Compares directories by the chunks of their files the same way
+/// `find_duplicates` compares them by files: every chunk adds its length to
+/// the shared size of all pairs of directories it is found in. Pairs whose
+/// containment is below `min_containment` are left out, the rest is sorted
+/// from the biggest shared size.
+pub(crate) fn find_near_duplicates(
+    result: &ScanResult,
+    min_containment: f64,
+) -> Vec<NearDuplicate> {
+    let mut dirs: Vec<&String> = result.dir_hashes.keys().collect();
+    dirs.sort();
+
+    let mut dir_sizes: Vec<usize> = Vec::with_capacity(dirs.len());
+    let mut chunk_dirs: HashMap<u64, (usize, Vec<u32>)> = HashMap::new();
+    for (id, dir) in dirs.iter().enumerate() {
+        let mut dir_chunks: HashMap<u64, usize> = HashMap::new();
+        for hash in result.dir_hashes[*dir].iter() {
+            dir_chunks.extend(result.group_chunks[*hash as usize].iter().copied());
+        }
+        dir_sizes.push(dir_chunks.values().sum());
+        for (chunk, length) in dir_chunks {
+            chunk_dirs
+                .entry(chunk)
+                .or_insert_with(|| (length, Vec::new()))
+                .1
+                .push(id as u32);
+        }
+    }
+
+    let mut pair_sizes: HashMap<(u32, u32), usize> = HashMap::new();
+    for (length, ids) in chunk_dirs.values() {
+        for (i, id1) in ids.iter().enumerate() {
+            for id2 in ids[i + 1..].iter() {
+                *pair_sizes.entry((*id1, *id2)).or_insert(0) += length;
+            }
+        }
+    }
+
+    let mut near_duplicates: Vec<NearDuplicate> = pair_sizes
+        .into_iter()
+        .map(|((id1, id2), shared_size)| {
+            let (dir1, dir2) = (dirs[id1 as usize], dirs[id2 as usize]);
+            let (size1, size2) = (dir_sizes[id1 as usize], dir_sizes[id2 as usize]);
+            NearDuplicate {
+                dir1: dir1.clone(),
+                dir2: dir2.clone(),
+                dir1_files_number: result.dir_hashes[dir1].len(),
+                dir2_files_number: result.dir_hashes[dir2].len(),
+                dir1_size: size1,
+                dir2_size: size2,
+                shared_size,
+                jaccard: shared_size as f64 / (size1 + size2 - shared_size) as f64,
+                containment: shared_size as f64 / size1.min(size2) as f64,
+            }
+        })
+        .filter(|near| near.containment >= min_containment)
+        .collect();
+    near_duplicates.sort_by(|a, b| {
+        b.shared_size
+            .cmp(&a.shared_size)
+            .then_with(|| (&a.dir1, &a.dir2).cmp(&(&b.dir1, &b.dir2)))
+    });
+    near_duplicates
+}
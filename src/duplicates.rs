@@ -0,0 +1,568 @@
+use crate::hash::Digest;
+use crate::policy::{Decision, Policy};
+use crate::scan::{FileInfo, ScanResult};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Files with the same contents found in both directories of a `Duplicate`.
+#[derive(Serialize)]
+pub struct SharedFile {
+    pub size: usize,
+    pub dir1_files: Vec<String>,
+    pub dir2_files: Vec<String>,
+    /// All the files are hardlinks of the same inode, so they take no extra space.
+    pub hardlinked: bool,
+    /// The files are similar images found by a perceptual hash, and neither
+    /// directory has an exact copy of a file of the other one.
+    pub similar: bool,
+}
+
+/// Two directories with some files in common.
+#[derive(Serialize)]
+pub struct Duplicate {
+    pub dir1: String,
+    pub dir2: String,
+    pub dir1_files_number: usize,
+    pub dir2_files_number: usize,
+    pub intersection: usize,
+    pub dir1_size: usize,
+    pub dir2_size: usize,
+    /// Size of shared files which are not hardlinks of each other yet.
+    pub shared_size: usize,
+    /// Intersection divided by the number of distinct files in both directories.
+    pub jaccard: f64,
+    /// Intersection divided by the number of files in the smaller directory.
+    pub containment: f64,
+    /// Only identical files count, so directories sharing similar images
+    /// overlap even when they hold the same pictures.
+    pub relation: Relation,
+    /// Set by the keep policy once all duplicates are found, `None` when no
+    /// keep rules are configured.
+    pub decision: Option<Decision>,
+    /// All shared files are hardlinks between the directories, removing
+    /// either of them would free no space.
+    pub hardlinked: bool,
+    pub shared_files: Vec<SharedFile>,
+}
+
+/// How files of `dir1` of a `Duplicate` relate to files of `dir2`.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Relation {
+    Identical,
+    /// Every file of `dir1` is also in `dir2`.
+    Subset,
+    /// Every file of `dir2` is also in `dir1`.
+    Superset,
+    Overlap,
+}
+
+impl Relation {
+    pub(crate) fn new(
+        dir1_files_number: usize,
+        dir2_files_number: usize,
+        intersection: usize,
+    ) -> Relation {
+        match (
+            intersection == dir1_files_number,
+            intersection == dir2_files_number,
+        ) {
+            (true, true) => Relation::Identical,
+            (true, false) => Relation::Subset,
+            (false, true) => Relation::Superset,
+            (false, false) => Relation::Overlap,
+        }
+    }
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Relation::Identical => "identical",
+            Relation::Subset => "subset",
+            Relation::Superset => "superset",
+            Relation::Overlap => "overlap",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A directory all files of which, also in its subdirectories, are also found
+/// outside of it. `files_number` and `size` count distinct contents of the
+/// whole tree.
+#[derive(Serialize)]
+pub struct RedundantDir {
+    pub dir: String,
+    pub files_number: usize,
+    pub size: usize,
+    pub found_in: Vec<String>,
+}
+
+/// Files with identical contents, found anywhere in the scanned directories.
+/// With a perceptual hash, similar images, `size` being the biggest of them.
+#[derive(Clone, Debug)]
+pub struct DuplicateSet {
+    pub size: usize,
+    /// Checksum of the whole contents, unknown when the files were told apart
+    /// from all others before it was computed, e.g. when they are hardlinks,
+    /// or when the set holds similar images rather than identical files.
+    pub hash: Option<Digest>,
+    pub files: Vec<String>,
+}
+
+pub(crate) fn find_duplicate_sets(result: &ScanResult) -> Vec<DuplicateSet> {
+    let mut duplicate_sets: Vec<DuplicateSet> = result
+        .groups
+        .iter()
+        .filter(|group| group.len() > 1)
+        .map(|group| {
+            let mut files: Vec<String> = group.iter().map(|file| file.path.clone()).collect();
+            files.sort();
+            DuplicateSet {
+                size: group[0].size,
+                hash: group
+                    .iter()
+                    .map(|file| file.full_hash)
+                    .reduce(|a, b| if a == b { a } else { None })
+                    .flatten(),
+                files,
+            }
+        })
+        .collect();
+    duplicate_sets.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.files.cmp(&b.files)));
+    duplicate_sets
+}
+
+/// Metric by which `sort_duplicates` orders duplicates.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortBy {
+    Intersection,
+    Jaccard,
+    Containment,
+    SharedSize,
+}
+
+impl SortBy {
+    pub const VARIANTS: &'static [&'static str] =
+        &["intersection", "jaccard", "containment", "shared_size"];
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SortBy, String> {
+        match s {
+            "intersection" => Ok(SortBy::Intersection),
+            "jaccard" => Ok(SortBy::Jaccard),
+            "containment" => Ok(SortBy::Containment),
+            "shared_size" => Ok(SortBy::SharedSize),
+            _ => Err(format!("unknown sort order: {}", s)),
+        }
+    }
+}
+
+/// Sorts the best duplicates first, ties are broken by the number of shared files.
+pub fn sort_duplicates(duplicates: &mut [Duplicate], sort_by: SortBy) {
+    duplicates.sort_by(|a, b| {
+        let order = match sort_by {
+            SortBy::Intersection => a.intersection.cmp(&b.intersection),
+            SortBy::Jaccard => a.jaccard.total_cmp(&b.jaccard),
+            SortBy::Containment => a.containment.total_cmp(&b.containment),
+            SortBy::SharedSize => a.shared_size.cmp(&b.shared_size),
+        };
+        order.then(a.intersection.cmp(&b.intersection)).reverse()
+    });
+}
+
+fn files_in_dir(group: &[FileInfo], dir: &str) -> Vec<String> {
+    group
+        .iter()
+        .filter(|file| file.dir == dir)
+        .map(|file| file.path.clone())
+        .collect()
+}
+
+/// Size of distinct file contents of `dir`, every group of identical files is
+/// counted once, by the size of its first file in `dir`.
+fn contents_size(groups: &[Vec<FileInfo>], hashes: &HashSet<u64>, dir: &str) -> usize {
+    hashes
+        .iter()
+        .filter_map(|hash| groups[*hash as usize].iter().find(|file| file.dir == dir))
+        .map(|file| file.size)
+        .sum()
+}
+
+/// Compares every pair of directories which have at least one file in common.
+/// `hash_dirs` is used as an inverted index: for every file all pairs of its
+/// directories get their counter of common files incremented, so directories
+/// which share nothing are never compared.
+pub(crate) fn find_duplicates(result: &ScanResult, min_intersection: usize) -> Vec<Duplicate> {
+    let groups = &result.groups;
+    let hash_dirs = &result.hash_dirs;
+    let dir_hashes = &result.dir_hashes;
+    // Directories with less files than `min_intersection` can't be reported
+    // and are left out of the index.
+    let mut dirs: Vec<&String> = dir_hashes
+        .iter()
+        .filter(|(_, hashes)| hashes.len() >= min_intersection)
+        .map(|(dir, _)| dir)
+        .collect();
+    dirs.sort();
+    let dir_ids: HashMap<&String, u32> = dirs
+        .iter()
+        .enumerate()
+        .map(|(id, dir)| (*dir, id as u32))
+        .collect();
+
+    let mut pair_counters: HashMap<(u32, u32), usize> = HashMap::new();
+    let mut ids: Vec<u32> = Vec::new();
+    for hash_dirs in hash_dirs.values() {
+        ids.clear();
+        ids.extend(hash_dirs.iter().filter_map(|dir| dir_ids.get(dir)));
+        ids.sort_unstable();
+        for (i, id1) in ids.iter().enumerate() {
+            for id2 in ids[i + 1..].iter() {
+                *pair_counters.entry((*id1, *id2)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut duplicates: Vec<Duplicate> = pair_counters
+        .into_iter()
+        .filter(|(_, intersection)| *intersection >= min_intersection)
+        .map(|((id1, id2), intersection)| {
+            let dir1 = dirs[id1 as usize];
+            let dir2 = dirs[id2 as usize];
+            let hashes1 = &dir_hashes[dir1];
+            let hashes2 = &dir_hashes[dir2];
+
+            let mut shared_files: Vec<SharedFile> = hashes1
+                .intersection(hashes2)
+                .map(|hash| {
+                    let group = &groups[*hash as usize];
+                    let mut inodes = group
+                        .iter()
+                        .filter(|file| file.dir == *dir1 || file.dir == *dir2)
+                        .map(|file| (file.device, file.inode));
+                    let first = inodes.next();
+                    SharedFile {
+                        size: group[0].size,
+                        dir1_files: files_in_dir(group, dir1),
+                        dir2_files: files_in_dir(group, dir2),
+                        hardlinked: first.is_some_and(|first| {
+                            first.1 != 0 && inodes.all(|inode| inode == first)
+                        }),
+                        similar: result.similar.contains(hash)
+                            && !group.iter().any(|file1| {
+                                file1.dir == *dir1
+                                    && group.iter().any(|file2| {
+                                        file2.dir == *dir2 && file2.content == file1.content
+                                    })
+                            }),
+                    }
+                })
+                .collect();
+            shared_files.sort_by(|a, b| a.dir1_files.cmp(&b.dir1_files));
+
+            let identical = shared_files.iter().filter(|file| !file.similar).count();
+            let union = hashes1.len() + hashes2.len() - intersection;
+            let smaller = hashes1.len().min(hashes2.len());
+            Duplicate {
+                dir1: dir1.clone(),
+                dir2: dir2.clone(),
+                dir1_files_number: hashes1.len(),
+                dir2_files_number: hashes2.len(),
+                intersection,
+                dir1_size: contents_size(groups, hashes1, dir1),
+                dir2_size: contents_size(groups, hashes2, dir2),
+                shared_size: shared_files
+                    .iter()
+                    .filter(|file| !file.hardlinked)
+                    .map(|file| file.size)
+                    .sum(),
+                jaccard: intersection as f64 / union as f64,
+                containment: intersection as f64 / smaller as f64,
+                relation: Relation::new(hashes1.len(), hashes2.len(), identical),
+                decision: None,
+                hardlinked: shared_files.iter().all(|file| file.hardlinked),
+                shared_files,
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| (&a.dir1, &a.dir2).cmp(&(&b.dir1, &b.dir2)));
+    duplicates
+}
+
+/// `dir` and its parents up to the scanned directory it was found in.
+pub(crate) fn dir_and_parents<'a>(
+    dir: &'a str,
+    roots: &'a [String],
+) -> impl Iterator<Item = &'a str> {
+    let mut root_reached = false;
+    Path::new(dir).ancestors().map_while(move |path| {
+        if root_reached || path.as_os_str().is_empty() {
+            return None;
+        }
+        root_reached = roots.iter().any(|root| Path::new(root) == path);
+        path.to_str()
+    })
+}
+
+/// Finds directories whose every file, also in subdirectories, has a copy
+/// outside of the directory. The copies may be spread over several
+/// directories, which are listed in `found_in`. Only the topmost of nested
+/// redundant directories is reported, since removing it removes the others.
+/// Directories are taken in the order the policy least prefers to keep them,
+/// and one whose files have copies only in directories taken before it is left
+/// out, the way `actions::delete_dirs` skips it, so all reported directories
+/// can be removed together. Only exact copies count, images a perceptual hash found similar
+/// are not copies. Directories which are protected by `policy` or contain protected
+/// directories are left out.
+pub(crate) fn find_redundant_dirs(result: &ScanResult, policy: &Policy) -> Vec<RedundantDir> {
+    let roots = result.directories();
+    let mut contents: HashMap<u64, Vec<&FileInfo>> = HashMap::new();
+    for file in result.groups.iter().flatten() {
+        contents.entry(file.content).or_default().push(file);
+    }
+
+    // How many files of every content are in the whole tree of each directory.
+    let mut tree_counts: HashMap<&str, HashMap<u64, usize>> = HashMap::new();
+    for (content, files) in contents.iter() {
+        for file in files.iter() {
+            for dir in dir_and_parents(&file.dir, roots) {
+                *tree_counts
+                    .entry(dir)
+                    .or_default()
+                    .entry(*content)
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut protected: HashSet<&str> = HashSet::new();
+    for dir in tree_counts.keys().filter(|dir| policy.is_protected(dir)) {
+        protected.extend(dir_and_parents(dir, roots));
+    }
+    let redundant: HashSet<&str> = tree_counts
+        .iter()
+        .filter(|(dir, _)| !protected.contains(*dir))
+        .filter(|(_, counts)| {
+            counts
+                .iter()
+                .all(|(content, count)| *count < contents[content].len())
+        })
+        .map(|(dir, _)| *dir)
+        .collect();
+
+    let mut candidates: Vec<(&str, usize)> = redundant
+        .iter()
+        .filter(|dir| {
+            !dir_and_parents(dir, roots)
+                .skip(1)
+                .any(|parent| redundant.contains(parent))
+        })
+        .map(|dir| {
+            let size = tree_counts[dir]
+                .keys()
+                .filter_map(|content| {
+                    contents[content]
+                        .iter()
+                        .find(|file| Path::new(&file.dir).starts_with(dir))
+                })
+                .map(|file| file.size)
+                .sum();
+            (*dir, size)
+        })
+        .collect();
+    candidates.sort_by(|(dir1, size1), (dir2, size2)| {
+        policy
+            .compare(dir1, dir2)
+            .then_with(|| size2.cmp(size1))
+            .then_with(|| dir1.cmp(dir2))
+    });
+
+    let mut redundant_dirs: Vec<RedundantDir> = Vec::new();
+    for (dir, size) in candidates {
+        let counts = &tree_counts[dir];
+        let kept = |file: &FileInfo| {
+            !Path::new(&file.dir).starts_with(dir)
+                && !redundant_dirs
+                    .iter()
+                    .any(|listed| Path::new(&file.dir).starts_with(&listed.dir))
+        };
+        if !counts
+            .keys()
+            .all(|content| contents[content].iter().any(|file| kept(file)))
+        {
+            continue;
+        }
+        let found_in: BTreeSet<&String> = counts
+            .keys()
+            .flat_map(|content| contents[content].iter())
+            .filter(|file| kept(file))
+            .map(|file| &file.dir)
+            .collect();
+        redundant_dirs.push(RedundantDir {
+            dir: dir.to_string(),
+            files_number: counts.len(),
+            size,
+            found_in: found_in.into_iter().cloned().collect(),
+        });
+    }
+    redundant_dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perceptual::PerceptualHash;
+    use crate::Scanner;
+    use image::{imageops, Rgb, RgbImage};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dirdups-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn path(root: &Path, dir: &str) -> String {
+        String::from(root.join(dir).to_string_lossy())
+    }
+
+    /// Scans `files` with the contents given, created under a new temporary directory.
+    fn scan(name: &str, files: &[(&str, &str)]) -> (PathBuf, ScanResult) {
+        let root = temp_dir(name);
+        for (file, contents) in files.iter() {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let result = Scanner::new([root.to_string_lossy()]).scan().unwrap();
+        (root, result)
+    }
+
+    fn pairs(duplicates: &[Duplicate], root: &Path) -> Vec<(String, String)> {
+        let name =
+            |dir: &str| String::from(Path::new(dir).strip_prefix(root).unwrap().to_string_lossy());
+        duplicates
+            .iter()
+            .map(|x| (name(&x.dir1), name(&x.dir2)))
+            .collect()
+    }
+
+    #[test]
+    fn every_pair_of_dirs_sharing_a_file_is_found() {
+        let files = [
+            ("A/x", "same"),
+            ("B/x", "same"),
+            ("C/x", "same"),
+            ("D/x", "same"),
+        ];
+        let (root, result) = scan("pairs", &files);
+        let pairs = pairs(&result.duplicates(1), &root);
+        let expected = [
+            ("A", "B"),
+            ("A", "C"),
+            ("A", "D"),
+            ("B", "C"),
+            ("B", "D"),
+            ("C", "D"),
+        ];
+        assert_eq!(pairs.len(), expected.len());
+        for (dir1, dir2) in expected {
+            assert!(pairs.contains(&(dir1.into(), dir2.into())));
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn dirs_with_less_files_than_min_intersection_are_left_out() {
+        let (root, result) = scan(
+            "min-intersection",
+            &[
+                ("A/x", "x"),
+                ("A/y", "y"),
+                ("B/x", "x"),
+                ("B/y", "y"),
+                ("C/x", "x"),
+            ],
+        );
+        assert_eq!(
+            pairs(&result.duplicates(2), &root),
+            [("A".into(), "B".into())]
+        );
+        assert_eq!(result.duplicates(1).len(), 3);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    fn relation(duplicates: &[Duplicate], root: &Path, dir1: &str, dir2: &str) -> Relation {
+        duplicates
+            .iter()
+            .find(|x| x.dir1 == path(root, dir1) && x.dir2 == path(root, dir2))
+            .map(|x| x.relation)
+            .unwrap()
+    }
+
+    #[test]
+    fn exact_copies_stay_identical_among_similar_images() {
+        let root = temp_dir("similar");
+        for dir in ["A", "B", "C"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let image = RgbImage::from_fn(240, 180, |x, y| {
+            let gray = ((x * 255 / 240 + y * 255 / 180) / 2) as u8;
+            Rgb([gray, 255 - gray, gray / 2])
+        });
+        image.save(root.join("A/x.png")).unwrap();
+        image.save(root.join("B/x.png")).unwrap();
+        imageops::resize(&image, 120, 90, imageops::FilterType::Triangle)
+            .save(root.join("C/x.png"))
+            .unwrap();
+
+        let result = Scanner::new([root.to_string_lossy()])
+            .perceptual(PerceptualHash::DHash)
+            .scan()
+            .unwrap();
+        assert_eq!(result.groups.len(), 1);
+
+        let duplicates = result.duplicates(1);
+        assert_eq!(relation(&duplicates, &root, "A", "B"), Relation::Identical);
+        assert_eq!(relation(&duplicates, &root, "A", "C"), Relation::Overlap);
+
+        let policy = Policy::new(Vec::new(), &result);
+        let redundant: Vec<String> = result
+            .redundant_dirs(&policy)
+            .into_iter()
+            .map(|x| x.dir)
+            .collect();
+        assert_eq!(redundant, [path(&root, "A")]);
+
+        let trees = result.dir_trees();
+        assert!(trees.same_tree(&path(&root, "A"), &path(&root, "B")));
+        assert!(!trees.same_tree(&path(&root, "A"), &path(&root, "C")));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn redundant_dirs_can_all_be_removed() {
+        let root = temp_dir("redundant");
+        for dir in ["A/sub", "B/sub", "C/sub"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("x"), "same").unwrap();
+        }
+        let result = Scanner::new([root.to_string_lossy()]).scan().unwrap();
+        let policy = Policy::new(Vec::new(), &result);
+        let redundant = result.redundant_dirs(&policy);
+
+        let dirs: Vec<&str> = redundant.iter().map(|x| x.dir.as_str()).collect();
+        assert_eq!(dirs, [path(&root, "A"), path(&root, "B")]);
+        // The copies of B which stay are the ones in C.
+        assert_eq!(redundant[1].found_in, [path(&root, "C/sub")]);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why a file could not be scanned or changed, or why a scan could not start.
+#[derive(Debug)]
+pub enum Error {
+    PermissionDenied {
+        path: PathBuf,
+    },
+    /// The file was removed after it had been found, or is a broken symlink.
+    Vanished {
+        path: PathBuf,
+    },
+    Io {
+        path: Option<PathBuf>,
+        error: io::Error,
+    },
+    InvalidOption(String),
+}
+
+impl Error {
+    pub fn from_io(path: &Path, error: io::Error) -> Error {
+        let path = path.to_path_buf();
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path },
+            io::ErrorKind::NotFound => Error::Vanished { path },
+            _ => Error::Io {
+                path: Some(path),
+                error,
+            },
+        }
+    }
+
+    /// Short name of the kind of the error, used to count errors by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::PermissionDenied { .. } => "permission denied",
+            Error::Vanished { .. } => "vanished",
+            Error::Io { .. } => "I/O error",
+            Error::InvalidOption(_) => "invalid option",
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::PermissionDenied { path } | Error::Vanished { path } => Some(path),
+            Error::Io { path, .. } => path.as_deref(),
+            Error::InvalidOption(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::PermissionDenied { path } => write!(f, "{}: permission denied", path.display()),
+            Error::Vanished { path } => write!(f, "{}: no such file", path.display()),
+            Error::Io {
+                path: Some(path),
+                error,
+            } => write!(f, "{}: {}", path.display(), error),
+            Error::Io { path: None, error } => write!(f, "{}", error),
+            Error::InvalidOption(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
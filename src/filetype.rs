@@ -0,0 +1,93 @@
+use crate::scan::FileInfo;
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Kind of content, detected from the first bytes of a file rather than from
+/// its name, so renamed files are still recognized.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileType {
+    Image,
+    Video,
+    Audio,
+    Document,
+}
+
+impl FileType {
+    pub const VARIANTS: &'static [&'static str] = &["image", "video", "audio", "document"];
+
+    fn matches(self, kind: &infer::Type) -> bool {
+        use infer::MatcherType;
+        match self {
+            FileType::Image => kind.matcher_type() == MatcherType::Image,
+            FileType::Video => kind.matcher_type() == MatcherType::Video,
+            FileType::Audio => kind.matcher_type() == MatcherType::Audio,
+            FileType::Document => {
+                matches!(kind.matcher_type(), MatcherType::Doc | MatcherType::Book)
+                    || matches!(kind.extension(), "pdf" | "rtf")
+            }
+        }
+    }
+}
+
+impl FromStr for FileType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FileType, String> {
+        match s {
+            "image" => Ok(FileType::Image),
+            "video" => Ok(FileType::Video),
+            "audio" => Ok(FileType::Audio),
+            "document" => Ok(FileType::Document),
+            _ => Err(format!("unknown file type: {}", s)),
+        }
+    }
+}
+
+/// Restricts scanned files to some extensions and kinds of content.
+/// A file must pass both lists, an empty list lets every file through.
+pub(crate) struct TypeFilter {
+    extensions: HashSet<String>,
+    types: Vec<FileType>,
+}
+
+impl TypeFilter {
+    /// Extensions are compared case-insensitively, with or without a leading dot.
+    pub fn new(extensions: &[String], types: &[FileType]) -> TypeFilter {
+        TypeFilter {
+            extensions: extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect(),
+            types: types.to_vec(),
+        }
+    }
+
+    pub fn matches_extension(&self, path: &str) -> bool {
+        if self.extensions.is_empty() {
+            return true;
+        }
+        match Path::new(path).extension() {
+            Some(ext) => self
+                .extensions
+                .contains(&ext.to_string_lossy().to_lowercase()),
+            None => false,
+        }
+    }
+
+    /// Reads the first bytes of the file only when some types are required.
+    /// Symlinks scanned as their own entries have no type.
+    pub fn matches_content(&self, file: &FileInfo) -> io::Result<bool> {
+        if self.types.is_empty() {
+            return Ok(true);
+        }
+        if file.is_symlink {
+            return Ok(false);
+        }
+        Ok(match infer::get_from_path(&file.path)? {
+            Some(kind) => self.types.iter().any(|t| t.matches(&kind)),
+            None => false,
+        })
+    }
+}
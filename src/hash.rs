@@ -0,0 +1,134 @@
+use sha2::Digest as _;
+use std::fmt;
+use std::str::FromStr;
+
+pub const DIGEST_SIZE: usize = 32;
+
+/// Fingerprint of file contents. Algorithms producing shorter digests are padded with zeros.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub struct Digest([u8; DIGEST_SIZE]);
+
+impl Digest {
+    fn from_slice(bytes: &[u8]) -> Digest {
+        let mut digest = [0; DIGEST_SIZE];
+        digest[..bytes.len()].copy_from_slice(bytes);
+        Digest(digest)
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Digest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Digest, String> {
+        if s.len() != DIGEST_SIZE * 2 || !s.is_ascii() {
+            return Err(format!("invalid digest: {}", s));
+        }
+        let mut digest = [0; DIGEST_SIZE];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("invalid digest: {}", s))?;
+        }
+        Ok(Digest(digest))
+    }
+}
+
+pub trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> Digest;
+}
+
+impl ContentHasher for crc32fast::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        crc32fast::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Digest {
+        Digest::from_slice(&crc32fast::Hasher::finalize(*self).to_le_bytes())
+    }
+}
+
+impl ContentHasher for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Digest {
+        Digest::from_slice(&self.digest128().to_le_bytes())
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Digest {
+        Digest::from_slice(blake3::Hasher::finalize(&self).as_bytes())
+    }
+}
+
+impl ContentHasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Digest {
+        Digest::from_slice(&sha2::Digest::finalize(*self))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HashAlgorithm {
+    Crc32,
+    Xxh3,
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub const VARIANTS: &'static [&'static str] = &["crc32", "xxh3", "blake3", "sha256"];
+
+    pub fn hasher(self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Crc32 => Box::new(crc32fast::Hasher::new()),
+            HashAlgorithm::Xxh3 => Box::new(xxhash_rust::xxh3::Xxh3::new()),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Sha256 => Box::new(sha2::Sha256::new()),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HashAlgorithm, String> {
+        match s {
+            "crc32" => Ok(HashAlgorithm::Crc32),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(format!("unknown hash algorithm: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Crc32 => "crc32",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,32 @@
+//! Finds directories with the same files, no matter how the files are named
+//! or where they are. Files are compared by size, then by a checksum of their
+//! first bytes, then of their whole contents, so only files which may be
+//! identical are read in full.
+//!
+//! A [`Scanner`] walks the directories and returns a [`ScanResult`] which
+//! lists sets of identical files, pairs of directories with files in common
+//! and directories all files of which have copies elsewhere. Files may also be
+//! split into content-defined chunks to find directories whose files are only
+//! nearly identical, and images may be compared by perceptual hashes.
+
+pub mod actions;
+mod cache;
+mod chunks;
+mod duplicates;
+pub mod error;
+pub mod filetype;
+pub mod hash;
+pub mod observer;
+pub mod perceptual;
+pub mod policy;
+mod scan;
+pub mod tree;
+mod walk;
+
+pub use chunks::NearDuplicate;
+pub use duplicates::{
+    sort_duplicates, Duplicate, DuplicateSet, RedundantDir, Relation, SharedFile, SortBy,
+};
+pub use error::Error;
+pub use observer::{Event, Observer, Stage};
+pub use scan::{parse_size, FileInfo, ScanResult, Scanner, SizeRange};
@@ -1,14 +1,21 @@
-use crc32fast::Hasher;
+mod output;
+mod progress;
+mod tui;
+
+use dirdups::actions::{self, LinkKind, Removal, Summary};
+use dirdups::filetype::FileType;
+use dirdups::hash::HashAlgorithm;
+use dirdups::perceptual::PerceptualHash;
+use dirdups::policy::{self, Policy, Rule};
+use dirdups::{parse_size, sort_duplicates, Error, Scanner, SizeRange, SortBy};
 use humanize_rs::bytes::Bytes;
-use indicatif::ProgressBar;
-use indicatif::ProgressStyle;
-use std::cmp::Reverse;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{self, prelude::*};
-use std::path::Path;
+use output::{Column, Format};
+use progress::ProgressBarObserver;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use structopt::StructOpt;
-use walkdir::WalkDir;
 
 #[derive(StructOpt)]
 struct Cli {
@@ -22,6 +29,78 @@ struct Cli {
     )]
     min_size: String,
 
+    #[structopt(
+        long,
+        value_name = "N",
+        help = "Ignore files which are bigger than this size"
+    )]
+    max_size: Option<String>,
+
+    #[structopt(
+        long,
+        value_name = "RANGE",
+        number_of_values = 1,
+        help = "Scan only files with sizes in one of these ranges, written as MIN..MAX, MIN.. or ..MAX, e.g. 1GB.. or 10MB..100MB. Bounds are inclusive."
+    )]
+    size: Vec<SizeRange>,
+
+    #[structopt(
+        long,
+        value_name = "GLOB",
+        number_of_values = 1,
+        help = "Skip files and directories matching GLOB, directories with all their contents. Patterns from .dirdupsignore files are skipped too."
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(
+        long,
+        value_name = "GLOB",
+        number_of_values = 1,
+        help = "Scan only files matching GLOB"
+    )]
+    include: Vec<String>,
+
+    #[structopt(
+        long,
+        value_name = "EXT",
+        use_delimiter = true,
+        require_delimiter = true,
+        help = "Scan only files with these extensions, e.g. jpg,cr2,mp4"
+    )]
+    ext: Vec<String>,
+
+    #[structopt(
+        long = "type",
+        value_name = "TYPE",
+        use_delimiter = true,
+        require_delimiter = true,
+        possible_values = FileType::VARIANTS,
+        help = "Scan only files of these types, detected from their content rather than extension"
+    )]
+    file_type: Vec<FileType>,
+
+    #[structopt(
+        long,
+        help = "Skip files ignored by .gitignore and .ignore files, also in copies of repositories"
+    )]
+    gitignore: bool,
+
+    #[structopt(long, help = "Skip hidden files and directories")]
+    skip_hidden: bool,
+
+    #[structopt(
+        long,
+        help = "Follow symlinks to files and directories. Loops are reported and skipped, a file reachable by several paths is scanned once."
+    )]
+    follow_symlinks: bool,
+
+    #[structopt(
+        long,
+        conflicts_with = "follow-symlinks",
+        help = "Scan symlinks as files of their own, two symlinks are equal if they point to the same path"
+    )]
+    symlinks_as_entries: bool,
+
     #[structopt(
         short = "i",
         value_name = "N",
@@ -32,206 +111,255 @@ struct Cli {
     )]
     min_intersection: usize,
 
+    #[structopt(
+        long,
+        value_name = "RATIO",
+        default_value = "0",
+        help = "Minimal share of files found in both directories among all files of the two directories, from 0 to 1"
+    )]
+    min_similarity: f64,
+
+    #[structopt(
+        long,
+        value_name = "RATIO",
+        default_value = "0",
+        help = "Minimal share of files of the smaller directory which are also found in the bigger one, from 0 to 1"
+    )]
+    min_containment: f64,
+
+    #[structopt(
+        long,
+        value_name = "N",
+        default_value = "0",
+        help = "Minimal total size of files found in both directories, not counting files which are hardlinks of each other"
+    )]
+    min_shared_size: String,
+
+    #[structopt(
+        long,
+        help = "Report pairs of directories whose shared files are all hardlinks of each other after the other pairs. By default they are left out, since they take no extra space."
+    )]
+    show_hardlinked: bool,
+
+    #[structopt(
+        long,
+        value_name = "METRIC",
+        default_value = "intersection",
+        possible_values = SortBy::VARIANTS,
+        help = "Show duplicates with the biggest value of this metric first"
+    )]
+    sort_by: SortBy,
+
     #[structopt(
         short = "h",
         value_name = "N",
         long,
         required = true,
         default_value = "1024",
-        help = "Reads only N bytes to calculate checksum. Set 0 to read full file."
+        help = "Reads only N bytes to calculate the first checksum. Set 0 to skip this stage."
     )]
     head: String,
 
-    #[structopt(long, required = true, index = 1, help = "Directories to search")]
-    directories: Vec<String>,
-}
+    #[structopt(
+        long,
+        value_name = "ALGORITHM",
+        default_value = "xxh3",
+        possible_values = HashAlgorithm::VARIANTS,
+        help = "Checksum algorithm used to compare files"
+    )]
+    hash: HashAlgorithm,
 
-struct Duplicate {
-    dir1: String,
-    dir2: String,
-    dir1_files_number: usize,
-    dir2_files_number: usize,
-    intersection: usize,
-}
+    #[structopt(
+        long,
+        help = "Compare files byte by byte after their checksums match to rule out collisions"
+    )]
+    verify: bool,
 
-fn get_hash(filename: String, filesize: usize, read_first_bytes: usize) -> io::Result<u64> {
-    let crc32 = get_crc32_checksum(filename, read_first_bytes)?;
-    Ok(crc32 as u64 + filesize as u64)
-}
+    #[structopt(
+        long,
+        value_name = "HASH",
+        possible_values = PerceptualHash::VARIANTS,
+        help = "Count images with close perceptual hashes as the same file, so resized or re-encoded copies match. Images are recognized by extension and decoded whole."
+    )]
+    perceptual: Option<PerceptualHash>,
 
-fn get_crc32_checksum(filename: String, read_first_bytes: usize) -> io::Result<u32> {
-    let mut f = File::open(filename)?;
-    let mut hasher = Hasher::new();
-    const BUF_SIZE: usize = 1024;
-    let mut buffer: [u8; BUF_SIZE] = [0; BUF_SIZE];
+    #[structopt(
+        long,
+        value_name = "N",
+        default_value = "8",
+        help = "How many of the 64 bits of perceptual hashes of two images may differ, used with --perceptual"
+    )]
+    max_distance: u32,
 
-    let mut bytes_readed = 0;
-    loop {
-        if read_first_bytes > 0 && bytes_readed >= read_first_bytes {
-            break;
-        }
-        let n = f.read(&mut buffer[..])?;
-        if n == 0 {
-            break;
-        }
-        hasher.update(&buffer[0..n]);
-        bytes_readed += n;
-    }
-    Ok(hasher.finalize())
-}
+    #[structopt(
+        long,
+        help = "Report identical directory trees once at the highest level instead of their subdirectories"
+    )]
+    trees: bool,
 
-fn get_file_size(path: &String) -> io::Result<usize> {
-    Ok(File::open(path)?.metadata()?.len() as usize)
-}
+    #[structopt(
+        long,
+        conflicts_with = "trees",
+        help = "Review duplicates in an interactive terminal UI and delete or move some of them"
+    )]
+    interactive: bool,
 
-fn get_files(directories: Vec<String>) -> Vec<String> {
-    let mut files: Vec<String> = Vec::new();
-
-    for directory in directories.iter() {
-        for entry in WalkDir::new(directory)
-            .into_iter()
-            .filter_map(Result::ok)
-            .filter(|e| e.file_type().is_file())
-        {
-            let path = String::from(entry.path().to_string_lossy());
-            files.push(path);
-        }
-    }
-    files
-}
+    #[structopt(
+        long,
+        conflicts_with_all = &["trees", "interactive"],
+        help = "List directories whose every file is also found in other directories instead of pairs of duplicates"
+    )]
+    redundant: bool,
 
-fn load_files_info(
-    files: &Vec<String>,
-    min_size: usize,
-    head: usize,
-    hash_dirs: &mut HashMap<u64, HashSet<String>>,
-    dir_hashes: &mut HashMap<String, HashSet<u64>>,
-) {
-    let files_cnt = files.len();
-    println!("Found: {} files", files_cnt);
-
-    let progress_bar = ProgressBar::new(files_cnt as u64);
-    progress_bar.set_style(
-        ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:80} {pos}/{len}"),
-    );
+    #[structopt(
+        long,
+        conflicts_with_all = &["trees", "interactive"],
+        help = "Move files of redundant directories to trash after comparing them byte by byte with their copies"
+    )]
+    delete_redundant: bool,
 
-    for file in files.iter() {
-        let filesize = match get_file_size(file) {
-            Ok(filesize) => filesize,
-            Err(e) => {
-                eprintln!("Error: {}: {}", file, e);
-                continue;
-            }
-        };
-        if filesize < min_size {
-            continue;
-        }
+    #[structopt(
+        long,
+        conflicts_with_all = &["trees", "interactive", "redundant", "delete-redundant"],
+        help = "Report pairs of directories whose files share content-defined chunks, so files which differ in a few bytes still match. Reads all files whole."
+    )]
+    near_duplicates: bool,
 
-        let dir: String = match Path::new(file).parent() {
-            Some(path) => String::from(path.to_string_lossy()),
-            None => String::from(""),
-        };
-        let hash = match get_hash(file.clone(), filesize, head) {
-            Ok(hash) => hash,
-            Err(e) => {
-                eprintln!("Error: {}: {}", file, e);
-                continue;
-            }
-        };
-
-        if let Some(val) = hash_dirs.get_mut(&hash) {
-            val.insert(dir.clone());
-        } else {
-            let mut dirs = HashSet::new();
-            dirs.insert(dir.clone());
-            hash_dirs.insert(hash.clone(), dirs);
-        }
+    #[structopt(
+        long,
+        value_name = "N",
+        default_value = "64KB",
+        help = "Average size of chunks compared by --near-duplicates, from 256 bytes to 4MB"
+    )]
+    chunk_size: String,
 
-        if let Some(val) = dir_hashes.get_mut(&dir) {
-            val.insert(hash.clone());
-        } else {
-            let mut hashes = HashSet::new();
-            hashes.insert(hash.clone());
-            dir_hashes.insert(dir, hashes);
-        }
+    #[structopt(
+        long,
+        value_name = "RATIO",
+        default_value = "0.5",
+        help = "Minimal share of the chunks of the smaller directory which are also found in the other one, used with --near-duplicates"
+    )]
+    min_shared_chunks: f64,
 
-        progress_bar.inc(1);
-    }
-    progress_bar.finish();
-}
+    #[structopt(
+        long,
+        value_name = "KIND",
+        possible_values = LinkKind::VARIANTS,
+        conflicts_with_all = &["trees", "interactive", "redundant", "delete-redundant", "near-duplicates"],
+        help = "Replace duplicate files of reported directory pairs with hardlinks or copy-on-write reflinks"
+    )]
+    link: Option<LinkKind>,
 
-fn find_duplicates(
-    hash_dirs: &HashMap<u64, HashSet<String>>,
-    dir_hashes: &HashMap<String, HashSet<u64>>,
-) -> Vec<Duplicate> {
-    let mut duplicates = Vec::new();
-    let mut added = HashSet::new();
-
-    for (_, dirs) in hash_dirs.iter() {
-        let mut dirs_iter = dirs.iter();
-        let mut prev_dir: &String = match dirs_iter.next() {
-            Some(v) => v,
-            None => break,
-        };
-
-        for dir in dirs_iter {
-            let t = if *dir < *prev_dir {
-                (dir, prev_dir)
-            } else {
-                (prev_dir, dir)
-            };
-            if added.contains(&t) {
-                continue;
-            }
-            let files = dir_hashes.get(dir).unwrap();
-            let prev_files = dir_hashes.get(prev_dir).unwrap();
-            let intersection: HashSet<_> = files.intersection(&prev_files).collect();
-            let duplicate = Duplicate {
-                dir1: String::from(dir),
-                dir2: String::from(prev_dir),
-                dir1_files_number: files.len(),
-                dir2_files_number: prev_files.len(),
-                intersection: intersection.len(),
-            };
-            duplicates.push(duplicate);
-
-            added.insert(t);
-            prev_dir = dir;
-        }
-    }
-    duplicates
-}
+    #[structopt(
+        long,
+        value_name = "RULE",
+        number_of_values = 1,
+        help = "Rule choosing which directory of a pair to keep, earlier rules win. One of under:PATH, older, newer, shorter-path, longer-path or protect:TEXT."
+    )]
+    keep: Vec<Rule>,
 
-fn print_duplicates(duplicates: &Vec<Duplicate>) {
-    for duplicate in duplicates.iter() {
-        println!(
-            "{}: {} - {}: {} | {}",
-            duplicate.dir1,
-            duplicate.dir1_files_number,
-            duplicate.dir2,
-            duplicate.dir2_files_number,
-            duplicate.intersection
-        )
-    }
+    #[structopt(
+        long,
+        value_name = "FILE",
+        help = "Read keep rules from FILE, one per line, after the ones given with --keep"
+    )]
+    policy: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Only print what --delete-redundant, --link or --interactive would do"
+    )]
+    dry_run: bool,
+
+    #[structopt(
+        long,
+        requires = "delete-redundant",
+        help = "Delete files permanently instead of moving them to trash"
+    )]
+    no_trash: bool,
+
+    #[structopt(
+        short = "f",
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        possible_values = Format::VARIANTS,
+        help = "Report format. jsonl prints every duplicate as a separate JSON document on its own line."
+    )]
+    format: Format,
+
+    #[structopt(
+        long,
+        value_name = "COLUMNS",
+        use_delimiter = true,
+        require_delimiter = true,
+        possible_values = Column::VARIANTS,
+        help = "Additional columns of csv and tsv reports"
+    )]
+    columns: Vec<Column>,
+
+    #[structopt(
+        long,
+        value_name = "FILE",
+        parse(from_os_str),
+        help = "Write all errors to FILE as CSV with kind, path and message columns"
+    )]
+    errors_to: Option<PathBuf>,
+
+    #[structopt(long, help = "Don't read or update the checksum cache")]
+    no_cache: bool,
+
+    #[structopt(
+        short = "j",
+        value_name = "N",
+        long,
+        default_value = "0",
+        help = "Number of threads used to read files. Set 0 to use one thread per CPU."
+    )]
+    jobs: usize,
+
+    #[structopt(long, required = true, index = 1, help = "Directories to search")]
+    directories: Vec<String>,
 }
 
-fn main() {
-    let args = Cli::from_args();
+/// Exit status when duplicates were found.
+const FOUND: i32 = 0;
+/// Exit status when the run went fine but found nothing.
+const NOT_FOUND: i32 = 1;
+/// Exit status when options were invalid, the scan could not start or the
+/// report could not be written. Files which could not be read or changed are
+/// only counted in the error summary.
+const FAILED: i32 = 2;
 
+fn invalid_value(option: &str, value: &str) -> Error {
+    Error::InvalidOption(format!("invalid value for '--{}': {}", option, value))
+}
+
+/// Runs the scan and the requested action, returns whether something was found.
+fn run(args: &Cli, reporter: &Arc<ProgressBarObserver>) -> Result<bool, Error> {
     let min_size = match args.min_size.parse::<Bytes>() {
         Ok(some) => some.size(),
+        Err(_) => return Err(invalid_value("min-size", &args.min_size)),
+    };
+
+    let max_size = match args.max_size.as_deref().map(parse_size).transpose() {
+        Ok(max_size) => max_size,
         Err(_) => {
-            eprintln!("Invalid value for '--min-size': {}.", args.min_size);
-            return;
+            return Err(invalid_value(
+                "max-size",
+                args.max_size.as_deref().unwrap_or_default(),
+            ))
         }
     };
 
+    let min_shared_size = match args.min_shared_size.parse::<Bytes>() {
+        Ok(some) => some.size(),
+        Err(_) => return Err(invalid_value("min-shared-size", &args.min_shared_size)),
+    };
+
     let mut head = match args.head.parse::<Bytes>() {
         Ok(some) => some.size(),
-        Err(_) => {
-            eprintln!("Invalid value for '--head': {}.", args.min_size);
-            return;
-        }
+        Err(_) => return Err(invalid_value("head", &args.head)),
     };
     if head > 0 && head < 1000 {
         head = 1024;
@@ -240,18 +368,201 @@ fn main() {
         );
     }
 
-    let mut hash_dirs: HashMap<u64, HashSet<String>> = HashMap::new();
-    let mut dir_hashes: HashMap<String, HashSet<u64>> = HashMap::new();
+    let chunk_size = match args.chunk_size.parse::<Bytes>() {
+        Ok(some) if args.near_duplicates => some.size(),
+        Ok(_) => 0,
+        Err(_) => return Err(invalid_value("chunk-size", &args.chunk_size)),
+    };
+
+    let mut rules = args.keep.clone();
+    if let Some(path) = &args.policy {
+        match policy::load_rules(path) {
+            Ok(file_rules) => rules.extend(file_rules),
+            Err(e) => return Err(Error::InvalidOption(format!("invalid policy file: {}", e))),
+        }
+    }
+
+    let mut scanner = Scanner::new(&args.directories);
+    scanner
+        .min_size(min_size)
+        .gitignore(args.gitignore)
+        .skip_hidden(args.skip_hidden)
+        .follow_symlinks(args.follow_symlinks)
+        .symlinks_as_entries(args.symlinks_as_entries)
+        .head(head)
+        .hash(args.hash)
+        .verify(args.verify)
+        .cache(!args.no_cache)
+        .chunk_size(chunk_size)
+        .max_distance(args.max_distance)
+        .jobs(args.jobs)
+        .observer(reporter.clone());
+    if let Some(max_size) = max_size {
+        scanner.max_size(max_size);
+    }
+    if let Some(kind) = args.perceptual {
+        scanner.perceptual(kind);
+    }
+    for range in args.size.iter() {
+        scanner.size_range(*range);
+    }
+    for glob in args.exclude.iter() {
+        scanner.exclude(glob);
+    }
+    for glob in args.include.iter() {
+        scanner.include(glob);
+    }
+    for extension in args.ext.iter() {
+        scanner.extension(extension);
+    }
+    for file_type in args.file_type.iter() {
+        scanner.file_type(*file_type);
+    }
+    let result = scanner.scan()?;
+
+    let policy = Policy::new(rules, &result);
+    let removal = if args.dry_run {
+        Removal::DryRun
+    } else if args.no_trash {
+        Removal::Unlink
+    } else {
+        Removal::Trash
+    };
+
+    if args.delete_redundant {
+        let redundant_dirs = result.redundant_dirs(&policy);
+        let summary = actions::delete_redundant(&redundant_dirs, &result, removal, &**reporter);
+        print_deleted(&summary, removal);
+        return Ok(!redundant_dirs.is_empty());
+    }
+
+    if args.redundant {
+        let redundant_dirs = result.redundant_dirs(&policy);
+        output::print_redundant_dirs(&redundant_dirs, args.format).map_err(report_error)?;
+        return Ok(!redundant_dirs.is_empty());
+    }
+
+    if args.near_duplicates {
+        let mut near_duplicates = result.near_duplicates(args.min_shared_chunks);
+        near_duplicates
+            .retain(|x| x.jaccard >= args.min_similarity && x.shared_size >= min_shared_size);
+        output::print_near_duplicates(&near_duplicates, args.format).map_err(report_error)?;
+        return Ok(!near_duplicates.is_empty());
+    }
+
+    let mut duplicates = result.duplicates(args.min_intersection);
+    duplicates.retain(|x| {
+        x.jaccard >= args.min_similarity
+            && x.containment >= args.min_containment
+            && x.shared_size >= min_shared_size
+            && (args.show_hardlinked || !x.hardlinked)
+    });
+
+    let mut duplicate_trees = Vec::new();
+    if args.trees {
+        let trees = result.dir_trees();
+        duplicates.retain(|x| !trees.same_tree(&x.dir1, &x.dir2));
+
+        duplicate_trees = trees.duplicates();
+        duplicate_trees.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.dirs.cmp(&b.dirs)));
+    }
+
+    // Without rules the decision would only follow the order of the paths.
+    if policy.is_configured() {
+        for duplicate in duplicates.iter_mut() {
+            duplicate.decision =
+                Some(policy.decide(&duplicate.dir1, &duplicate.dir2, duplicate.relation));
+        }
+    }
+
+    sort_duplicates(&mut duplicates, args.sort_by);
+    duplicates.sort_by_key(|x| x.hardlinked);
+
+    if let Some(kind) = args.link {
+        let summary = actions::link_duplicates(
+            &duplicates,
+            &result,
+            &policy,
+            kind,
+            args.dry_run,
+            &**reporter,
+        );
+        println!(
+            "{}: {} files, {} bytes reclaimed",
+            if args.dry_run { "Would link" } else { "Linked" },
+            summary.files,
+            summary.size
+        );
+        return Ok(!duplicates.is_empty());
+    }
+
+    if args.interactive {
+        let plan =
+            tui::run(&duplicates, &policy).map_err(|error| Error::Io { path: None, error })?;
+        let summary = tui::apply(&plan, &result, removal, &**reporter);
+        if summary.dirs > 0 {
+            print_deleted(&summary, removal);
+        }
+        return Ok(!duplicates.is_empty());
+    }
 
-    let files = get_files(args.directories);
-    load_files_info(&files, min_size, head, &mut hash_dirs, &mut dir_hashes);
+    output::print_report(
+        &duplicate_trees,
+        &duplicates,
+        args.format,
+        &args.columns,
+        policy.is_configured(),
+    )
+    .map_err(report_error)?;
+    Ok(!duplicates.is_empty() || !duplicate_trees.is_empty())
+}
 
-    let mut duplicates: Vec<Duplicate> = find_duplicates(&hash_dirs, &dir_hashes)
-        .into_iter()
-        .filter(|x| x.intersection >= args.min_intersection)
-        .collect();
+fn print_deleted(summary: &Summary, removal: Removal) {
+    let verb = match removal {
+        Removal::DryRun => "Would delete",
+        Removal::Trash => "Moved to trash",
+        Removal::Unlink => "Deleted",
+    };
+    println!(
+        "{}: {} files, {} bytes in {} directories",
+        verb, summary.files, summary.size, summary.dirs
+    );
+}
 
-    duplicates.sort_by_key(|x| Reverse(x.intersection));
+fn report_error(error: io::Error) -> Error {
+    Error::Io {
+        path: None,
+        error: io::Error::new(error.kind(), format!("can't write report: {}", error)),
+    }
+}
 
-    print_duplicates(&duplicates);
+fn main() {
+    let args = match Cli::from_iter_safe(std::env::args_os()) {
+        Ok(args) => args,
+        // --help and --version are not failures.
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            process::exit(FAILED);
+        }
+    };
+
+    let reporter = Arc::new(ProgressBarObserver::new());
+    let status = match run(&args, &reporter) {
+        Ok(true) => FOUND,
+        Ok(false) => NOT_FOUND,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            FAILED
+        }
+    };
+
+    reporter.print_summary();
+    if let Some(path) = &args.errors_to {
+        if let Err(e) = reporter.write_errors(path) {
+            eprintln!("Error: can't write {}: {}.", path.display(), e);
+            process::exit(FAILED);
+        }
+    }
+    process::exit(status);
 }
@@ -0,0 +1,125 @@
+use crate::{DuplicateSet, Error};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Stages in which files are read, each one only reads files which the
+/// previous stages could not tell apart.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    /// Checksums of the first bytes of files.
+    Head,
+    /// Checksums of whole files.
+    Full,
+    /// Byte by byte comparison of files with equal checksums.
+    Verify,
+    /// Content-defined chunks of distinct contents, to find near-duplicates.
+    Chunk,
+    /// Perceptual hashes of distinct images, to match resized or re-encoded copies.
+    Image,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Stage::Head => "head",
+            Stage::Full => "full",
+            Stage::Verify => "verify",
+            Stage::Chunk => "chunk",
+            Stage::Image => "image",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What happens during a scan or an action, in the order it happens. `FileHashed`,
+/// `FileVerified` and `FileError` may come from several threads at once.
+#[derive(Debug)]
+pub enum Event<'a> {
+    WalkStarted {
+        directories: &'a [String],
+    },
+    WalkFinished {
+        files: usize,
+    },
+    /// `files` is the number of files which will be read in this stage.
+    StageStarted {
+        stage: Stage,
+        files: usize,
+    },
+    FileHashed {
+        stage: Stage,
+        path: &'a Path,
+    },
+    FileVerified {
+        path: &'a Path,
+    },
+    StageFinished {
+        stage: Stage,
+    },
+    /// The file is skipped, the scan or action goes on.
+    FileError {
+        error: &'a Error,
+    },
+    /// A followed symlink leads back to `ancestor`, so it is skipped. Files
+    /// below it are still scanned through `ancestor`.
+    SymlinkLoop {
+        path: &'a Path,
+        ancestor: &'a Path,
+    },
+    /// An action left `path` untouched, e.g. a redundant directory some file
+    /// of which has no verified copy.
+    Skipped {
+        path: &'a Path,
+        reason: &'a str,
+    },
+    /// A file of a redundant directory is removed, its identical `copy` is
+    /// kept. Nothing is removed in a dry run.
+    FileRemoved {
+        path: &'a Path,
+        copy: &'a Path,
+        dry_run: bool,
+    },
+    /// All scanned files of a redundant directory are removed. The directory
+    /// itself is `removed` unless files which were not scanned are left in it.
+    DirCleared {
+        path: &'a Path,
+        removed: bool,
+    },
+    /// A file is replaced by a link to its identical copy `source`. Nothing
+    /// is changed in a dry run.
+    FileLinked {
+        path: &'a Path,
+        source: &'a Path,
+        dry_run: bool,
+    },
+    /// A directory is moved into `target`, e.g. by the interactive review.
+    /// Nothing is changed in a dry run.
+    DirMoved {
+        path: &'a Path,
+        target: &'a Path,
+        dry_run: bool,
+    },
+    /// A set of identical files, reported once all stages are finished.
+    DuplicateFound {
+        set: &'a DuplicateSet,
+    },
+}
+
+/// Receives events of a scan or an action, e.g. to show its progress.
+pub trait Observer: Send + Sync {
+    fn event(&self, event: &Event);
+}
+
+impl<T: Observer + ?Sized> Observer for Arc<T> {
+    fn event(&self, event: &Event) {
+        (**self).event(event)
+    }
+}
+
+/// Ignores all events, used when no observer is set.
+pub(crate) struct Silent;
+
+impl Observer for Silent {
+    fn event(&self, _event: &Event) {}
+}
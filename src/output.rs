@@ -0,0 +1,395 @@
+use dirdups::tree::DuplicateTree;
+use dirdups::{Duplicate, NearDuplicate, RedundantDir, Relation};
+use serde::Serialize;
+use std::io::{self, prelude::*};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Text,
+    Json,
+    Jsonl,
+    Csv,
+    Tsv,
+}
+
+impl Format {
+    pub const VARIANTS: &'static [&'static str] = &["text", "json", "jsonl", "csv", "tsv"];
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// Optional columns of CSV and TSV reports.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Column {
+    Similarity,
+    Containment,
+    SharedSize,
+    Relation,
+    Hardlinked,
+}
+
+impl Column {
+    pub const VARIANTS: &'static [&'static str] = &[
+        "similarity",
+        "containment",
+        "shared_size",
+        "relation",
+        "hardlinked",
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Column::Similarity => "similarity",
+            Column::Containment => "containment",
+            Column::SharedSize => "shared_size",
+            Column::Relation => "relation",
+            Column::Hardlinked => "hardlinked",
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Column, String> {
+        match s {
+            "similarity" => Ok(Column::Similarity),
+            "containment" => Ok(Column::Containment),
+            "shared_size" => Ok(Column::SharedSize),
+            "relation" => Ok(Column::Relation),
+            "hardlinked" => Ok(Column::Hardlinked),
+            _ => Err(format!("unknown column: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    duplicate_trees: &'a [DuplicateTree],
+    duplicates: &'a [Duplicate],
+}
+
+/// A line of JSON Lines output, `type` tells what kind of record it holds.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'a> {
+    DuplicateTree(&'a DuplicateTree),
+    Duplicate(&'a Duplicate),
+}
+
+fn write_text(
+    out: &mut impl Write,
+    duplicate_trees: &[DuplicateTree],
+    duplicates: &[Duplicate],
+) -> io::Result<()> {
+    for tree in duplicate_trees.iter() {
+        writeln!(
+            out,
+            "{} | {} files, {} bytes",
+            tree.dirs.join(" = "),
+            tree.files_number,
+            tree.size
+        )?;
+    }
+    for duplicate in duplicates.iter() {
+        write!(
+            out,
+            "{}: {} - {}: {} | {} | {}",
+            duplicate.dir1,
+            duplicate.dir1_files_number,
+            duplicate.dir2,
+            duplicate.dir2_files_number,
+            duplicate.intersection,
+            duplicate.relation
+        )?;
+        if duplicate.hardlinked {
+            write!(out, " | hardlinked")?;
+        }
+        if let Some(decision) = &duplicate.decision {
+            write!(out, " | keep: {}", decision.keep)?;
+            if let Some(redundant) = &decision.redundant {
+                write!(out, ", redundant: {}", redundant)?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_jsonl(
+    out: &mut impl Write,
+    duplicate_trees: &[DuplicateTree],
+    duplicates: &[Duplicate],
+) -> io::Result<()> {
+    let records = duplicate_trees
+        .iter()
+        .map(Record::DuplicateTree)
+        .chain(duplicates.iter().map(Record::Duplicate));
+    for record in records {
+        serde_json::to_writer(&mut *out, &record)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+struct Row<'a> {
+    dir1: &'a str,
+    dir1_files_number: usize,
+    dir2: &'a str,
+    dir2_files_number: usize,
+    intersection: usize,
+    similarity: f64,
+    containment: f64,
+    shared_size: usize,
+    relation: Relation,
+    hardlinked: bool,
+    keep: Option<&'a str>,
+    redundant: Option<&'a str>,
+}
+
+impl<'a> Row<'a> {
+    fn fields(&self, columns: &[Column], show_decisions: bool) -> Vec<String> {
+        let mut fields = vec![
+            self.dir1.to_string(),
+            self.dir1_files_number.to_string(),
+            self.dir2.to_string(),
+            self.dir2_files_number.to_string(),
+            self.intersection.to_string(),
+        ];
+        for column in columns.iter() {
+            fields.push(match column {
+                Column::Similarity => format!("{:.4}", self.similarity),
+                Column::Containment => format!("{:.4}", self.containment),
+                Column::SharedSize => self.shared_size.to_string(),
+                Column::Relation => self.relation.to_string(),
+                Column::Hardlinked => self.hardlinked.to_string(),
+            });
+        }
+        if show_decisions {
+            fields.push(self.keep.unwrap_or_default().to_string());
+            fields.push(self.redundant.unwrap_or_default().to_string());
+        }
+        fields
+    }
+}
+
+/// Identical trees are written as pairs of the first directory of a group with
+/// each of the others, so every row has the same columns.
+fn write_delimited(
+    out: &mut impl Write,
+    duplicate_trees: &[DuplicateTree],
+    duplicates: &[Duplicate],
+    delimiter: u8,
+    columns: &[Column],
+    show_decisions: bool,
+) -> io::Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(out);
+
+    let mut header = vec![
+        "dir1",
+        "dir1_files_number",
+        "dir2",
+        "dir2_files_number",
+        "intersection",
+    ];
+    header.extend(columns.iter().map(|column| column.name()));
+    if show_decisions {
+        header.extend(["keep", "redundant"]);
+    }
+    writer.write_record(&header)?;
+
+    let tree_rows = duplicate_trees.iter().flat_map(|tree| {
+        tree.dirs[1..].iter().map(move |dir| Row {
+            dir1: &tree.dirs[0],
+            dir1_files_number: tree.files_number,
+            dir2: dir,
+            dir2_files_number: tree.files_number,
+            intersection: tree.files_number,
+            similarity: 1.0,
+            containment: 1.0,
+            shared_size: tree.size,
+            relation: Relation::Identical,
+            hardlinked: false,
+            keep: None,
+            redundant: None,
+        })
+    });
+    let duplicate_rows = duplicates.iter().map(|duplicate| Row {
+        dir1: &duplicate.dir1,
+        dir1_files_number: duplicate.dir1_files_number,
+        dir2: &duplicate.dir2,
+        dir2_files_number: duplicate.dir2_files_number,
+        intersection: duplicate.intersection,
+        similarity: duplicate.jaccard,
+        containment: duplicate.containment,
+        shared_size: duplicate.shared_size,
+        relation: duplicate.relation,
+        hardlinked: duplicate.hardlinked,
+        keep: duplicate.decision.as_ref().map(|d| d.keep.as_str()),
+        redundant: duplicate
+            .decision
+            .as_ref()
+            .and_then(|d| d.redundant.as_deref()),
+    });
+    for row in tree_rows.chain(duplicate_rows) {
+        writer.write_record(row.fields(columns, show_decisions))?;
+    }
+    writer.flush()
+}
+
+pub fn print_report(
+    duplicate_trees: &[DuplicateTree],
+    duplicates: &[Duplicate],
+    format: Format,
+    columns: &[Column],
+    show_decisions: bool,
+) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match format {
+        Format::Text => write_text(&mut out, duplicate_trees, duplicates)?,
+        Format::Json => {
+            let report = Report {
+                duplicate_trees,
+                duplicates,
+            };
+            serde_json::to_writer_pretty(&mut out, &report)?;
+            writeln!(out)?;
+        }
+        Format::Jsonl => write_jsonl(&mut out, duplicate_trees, duplicates)?,
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+            write_delimited(
+                &mut out,
+                duplicate_trees,
+                duplicates,
+                delimiter,
+                columns,
+                show_decisions,
+            )?
+        }
+    }
+    out.flush()
+}
+
+pub fn print_redundant_dirs(redundant_dirs: &[RedundantDir], format: Format) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match format {
+        Format::Text => {
+            for redundant in redundant_dirs.iter() {
+                writeln!(
+                    out,
+                    "{}: {} files, {} bytes | {}",
+                    redundant.dir,
+                    redundant.files_number,
+                    redundant.size,
+                    redundant.found_in.join(", ")
+                )?;
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, redundant_dirs)?;
+            writeln!(out)?;
+        }
+        Format::Jsonl => {
+            for redundant in redundant_dirs.iter() {
+                serde_json::to_writer(&mut out, redundant)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(&mut out);
+            writer.write_record(["dir", "files_number", "size", "found_in"])?;
+            for redundant in redundant_dirs.iter() {
+                writer.write_record([
+                    redundant.dir.clone(),
+                    redundant.files_number.to_string(),
+                    redundant.size.to_string(),
+                    redundant.found_in.join(";"),
+                ])?;
+            }
+            writer.flush()?;
+        }
+    }
+    out.flush()
+}
+
+pub fn print_near_duplicates(near_duplicates: &[NearDuplicate], format: Format) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match format {
+        Format::Text => {
+            for near in near_duplicates.iter() {
+                writeln!(
+                    out,
+                    "{}: {} bytes - {}: {} bytes | {} bytes shared | {:.2}",
+                    near.dir1,
+                    near.dir1_size,
+                    near.dir2,
+                    near.dir2_size,
+                    near.shared_size,
+                    near.containment
+                )?;
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, near_duplicates)?;
+            writeln!(out)?;
+        }
+        Format::Jsonl => {
+            for near in near_duplicates.iter() {
+                serde_json::to_writer(&mut out, near)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(&mut out);
+            writer.write_record([
+                "dir1",
+                "dir1_files_number",
+                "dir1_size",
+                "dir2",
+                "dir2_files_number",
+                "dir2_size",
+                "shared_size",
+                "similarity",
+                "containment",
+            ])?;
+            for near in near_duplicates.iter() {
+                writer.write_record([
+                    near.dir1.clone(),
+                    near.dir1_files_number.to_string(),
+                    near.dir1_size.to_string(),
+                    near.dir2.clone(),
+                    near.dir2_files_number.to_string(),
+                    near.dir2_size.to_string(),
+                    near.shared_size.to_string(),
+                    format!("{:.4}", near.jaccard),
+                    format!("{:.4}", near.containment),
+                ])?;
+            }
+            writer.flush()?;
+        }
+    }
+    out.flush()
+}
@@ -0,0 +1,264 @@
+use crate::observer::{Event, Observer, Stage};
+use crate::scan::{file_error, FileInfo};
+use image::imageops::{self, FilterType};
+use image::{GrayImage, ImageFormat};
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How images are reduced to a 64 bit hash which changes little when an image
+/// is resized or re-encoded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PerceptualHash {
+    /// Whether each pixel of a 9x8 thumbnail is darker than its right neighbour.
+    DHash,
+    /// Whether each of the lowest 8x8 frequencies of a 32x32 thumbnail is
+    /// above their median. Slower than dHash but less sensitive to small edits.
+    PHash,
+}
+
+impl PerceptualHash {
+    pub const VARIANTS: &'static [&'static str] = &["dhash", "phash"];
+
+    fn hash(self, image: &GrayImage) -> u64 {
+        match self {
+            PerceptualHash::DHash => dhash(image),
+            PerceptualHash::PHash => phash(image),
+        }
+    }
+}
+
+impl FromStr for PerceptualHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PerceptualHash, String> {
+        match s {
+            "dhash" => Ok(PerceptualHash::DHash),
+            "phash" => Ok(PerceptualHash::PHash),
+            _ => Err(format!("unknown perceptual hash: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for PerceptualHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PerceptualHash::DHash => "dhash",
+            PerceptualHash::PHash => "phash",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn dhash(image: &GrayImage) -> u64 {
+    let small = imageops::resize(image, 9, 8, FilterType::Triangle);
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | (left < right) as u64;
+        }
+    }
+    hash
+}
+
+fn phash(image: &GrayImage) -> u64 {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+    let small = imageops::resize(image, SIZE as u32, SIZE as u32, FilterType::Triangle);
+    let cos: Vec<[f64; LOW]> = (0..SIZE)
+        .map(|n| std::array::from_fn(|k| (PI / SIZE as f64 * (n as f64 + 0.5) * k as f64).cos()))
+        .collect();
+
+    // Only the lowest frequencies are needed, so the DCT of rows is followed
+    // by the DCT of the first LOW columns.
+    let mut rows = [[0.0; LOW]; SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (k, value) in row.iter_mut().enumerate() {
+            *value = (0..SIZE)
+                .map(|x| small.get_pixel(x as u32, y as u32)[0] as f64 * cos[x][k])
+                .sum();
+        }
+    }
+    let mut coefficients = [0.0; LOW * LOW];
+    for k1 in 0..LOW {
+        for k2 in 0..LOW {
+            coefficients[k1 * LOW + k2] = (0..SIZE).map(|y| rows[y][k2] * cos[y][k1]).sum();
+        }
+    }
+
+    let mut sorted = coefficients;
+    sorted.sort_by(f64::total_cmp);
+    let median = (sorted[LOW * LOW / 2 - 1] + sorted[LOW * LOW / 2]) / 2.0;
+    coefficients
+        .iter()
+        .fold(0, |hash, value| hash << 1 | (*value > median) as u64)
+}
+
+/// Files of formats which can be decoded, recognized by their extension.
+fn is_image(file: &FileInfo) -> bool {
+    !file.is_symlink
+        && ImageFormat::from_path(&file.path).is_ok_and(|format| format.reading_enabled())
+}
+
+fn get_hash(file: &FileInfo, kind: PerceptualHash) -> io::Result<u64> {
+    let image = image::open(&file.path).map_err(|e| match e {
+        image::ImageError::IoError(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    })?;
+    Ok(kind.hash(&image.to_luma8()))
+}
+
+struct Node {
+    hash: u64,
+    /// Id of the group the hash was computed for.
+    id: usize,
+    /// Indexes of child nodes with their distance from this node.
+    children: Vec<(u32, usize)>,
+}
+
+/// A BK-tree of hashes, finds all hashes within a Hamming distance without
+/// comparing them with every other hash.
+struct BkTree {
+    nodes: Vec<Node>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, id: usize) {
+        let new = self.nodes.len();
+        self.nodes.push(Node {
+            hash,
+            id,
+            children: Vec::new(),
+        });
+        if new == 0 {
+            return;
+        }
+        let mut node = 0;
+        loop {
+            let distance = (self.nodes[node].hash ^ hash).count_ones();
+            match self.nodes[node]
+                .children
+                .iter()
+                .find(|(d, _)| *d == distance)
+            {
+                Some((_, child)) => node = *child,
+                None => {
+                    self.nodes[node].children.push((distance, new));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn find(&self, hash: u64, max_distance: u32, found: &mut Vec<usize>) {
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let Some(node) = self.nodes.get(node) else {
+                return;
+            };
+            let distance = (node.hash ^ hash).count_ones();
+            if distance <= max_distance {
+                found.push(node.id);
+            }
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| d.abs_diff(distance) <= max_distance)
+                    .map(|(_, child)| *child),
+            );
+        }
+    }
+}
+
+fn find_root(parents: &mut [usize], mut id: usize) -> usize {
+    while parents[id] != id {
+        parents[id] = parents[parents[id]];
+        id = parents[id];
+    }
+    id
+}
+
+/// Merges groups of images whose perceptual hashes differ in at most
+/// `max_distance` bits, so resized or re-encoded copies of a photo count as
+/// the same file. Images are matched transitively, and the biggest file of a
+/// merged group comes first. Only the first file of every group is decoded.
+/// Also returns the ids of merged groups, whose files may be only similar.
+/// Files keep the `content` id of the group they were in before merging.
+pub(crate) fn merge_similar_images(
+    groups: Vec<Vec<FileInfo>>,
+    kind: PerceptualHash,
+    max_distance: u32,
+    observer: &dyn Observer,
+) -> (Vec<Vec<FileInfo>>, HashSet<u64>) {
+    let images: Vec<usize> = (0..groups.len())
+        .filter(|id| is_image(&groups[*id][0]))
+        .collect();
+    observer.event(&Event::StageStarted {
+        stage: Stage::Image,
+        files: images.len(),
+    });
+    let hashes: Vec<(usize, u64)> = images
+        .into_par_iter()
+        .filter_map(|id| {
+            let file = &groups[id][0];
+            let hash = get_hash(file, kind);
+            observer.event(&Event::FileHashed {
+                stage: Stage::Image,
+                path: Path::new(&file.path),
+            });
+            match hash {
+                Ok(hash) => Some((id, hash)),
+                Err(e) => {
+                    file_error(observer, &file.path, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    observer.event(&Event::StageFinished {
+        stage: Stage::Image,
+    });
+
+    let mut parents: Vec<usize> = (0..groups.len()).collect();
+    let mut tree = BkTree { nodes: Vec::new() };
+    let mut similar = Vec::new();
+    for (id, hash) in hashes {
+        similar.clear();
+        tree.find(hash, max_distance, &mut similar);
+        for other in similar.iter() {
+            let root = find_root(&mut parents, *other);
+            let id_root = find_root(&mut parents, id);
+            parents[root] = id_root;
+        }
+        tree.insert(hash, id);
+    }
+
+    // Merged files with the number of groups they were merged from.
+    let mut merged: HashMap<usize, (Vec<FileInfo>, usize)> = HashMap::new();
+    for (id, group) in groups.into_iter().enumerate() {
+        let root = find_root(&mut parents, id);
+        let entry = merged.entry(root).or_default();
+        entry.0.extend(group);
+        entry.1 += 1;
+    }
+    let mut similar = HashSet::new();
+    let groups = merged
+        .into_values()
+        .enumerate()
+        .map(|(id, (mut group, merged_groups))| {
+            if merged_groups > 1 {
+                similar.insert(id as u64);
+            }
+            group.sort_by_key(|file| Reverse(file.size));
+            group
+        })
+        .collect();
+    (groups, similar)
+}
@@ -0,0 +1,246 @@
+use crate::{Relation, ScanResult};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A rule telling which of two directories is better to keep.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Rule {
+    /// Prefer directories inside this path.
+    Under(PathBuf),
+    /// Prefer directories with older files.
+    Older,
+    /// Prefer directories with newer files.
+    Newer,
+    ShorterPath,
+    LongerPath,
+    /// Never mark directories whose path contains this text as redundant.
+    Protect(String),
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (s, None),
+        };
+        match (name, value) {
+            ("under", Some(path)) if !path.is_empty() => Ok(Rule::Under(PathBuf::from(path))),
+            ("protect", Some(pattern)) if !pattern.is_empty() => {
+                Ok(Rule::Protect(String::from(pattern)))
+            }
+            ("older", None) => Ok(Rule::Older),
+            ("newer", None) => Ok(Rule::Newer),
+            ("shorter-path", None) => Ok(Rule::ShorterPath),
+            ("longer-path", None) => Ok(Rule::LongerPath),
+            _ => Err(format!("invalid rule: {}", s)),
+        }
+    }
+}
+
+/// Which directory of a `Duplicate` to keep and which one may be removed.
+/// `redundant` is only set when all of its files are in the kept directory.
+#[derive(Serialize)]
+pub struct Decision {
+    pub keep: String,
+    pub redundant: Option<String>,
+}
+
+/// Ordered keep rules, earlier rules win over later ones.
+pub struct Policy {
+    rules: Vec<Rule>,
+    /// Modification time of the oldest and the newest file of every directory.
+    mtimes: HashMap<String, (u128, u128)>,
+}
+
+/// Reads rules from a file, one per line. Empty lines and lines starting with `#` are skipped.
+pub fn load_rules(path: &str) -> Result<Vec<Rule>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse().map_err(|e| format!("{}: {}", path, e)))
+        .collect()
+}
+
+impl Policy {
+    pub fn new(rules: Vec<Rule>, result: &ScanResult) -> Policy {
+        let mut mtimes: HashMap<String, (u128, u128)> = HashMap::new();
+        for file in result.files() {
+            let entry = mtimes
+                .entry(file.dir.clone())
+                .or_insert((file.mtime, file.mtime));
+            entry.0 = entry.0.min(file.mtime);
+            entry.1 = entry.1.max(file.mtime);
+        }
+        Policy { rules, mtimes }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    pub fn is_protected(&self, dir: &str) -> bool {
+        let dir_path = format!("{}/", dir);
+        self.rules.iter().any(|rule| match rule {
+            Rule::Protect(pattern) => dir_path.contains(pattern.as_str()),
+            _ => false,
+        })
+    }
+
+    /// `Ordering::Greater` if `dir1` is better to keep than `dir2`.
+    /// Protected directories are always preferred.
+    pub fn compare(&self, dir1: &str, dir2: &str) -> Ordering {
+        let mut order = self.is_protected(dir1).cmp(&self.is_protected(dir2));
+        for rule in self.rules.iter() {
+            if order != Ordering::Equal {
+                break;
+            }
+            order = match rule {
+                Rule::Under(path) => {
+                    let under1 = Path::new(dir1).starts_with(path);
+                    let under2 = Path::new(dir2).starts_with(path);
+                    under1.cmp(&under2)
+                }
+                Rule::Older => {
+                    let oldest1 = self.mtimes.get(dir1).map(|m| m.0);
+                    let oldest2 = self.mtimes.get(dir2).map(|m| m.0);
+                    oldest2.cmp(&oldest1)
+                }
+                Rule::Newer => {
+                    let newest1 = self.mtimes.get(dir1).map(|m| m.1);
+                    let newest2 = self.mtimes.get(dir2).map(|m| m.1);
+                    newest1.cmp(&newest2)
+                }
+                Rule::ShorterPath => dir2.len().cmp(&dir1.len()),
+                Rule::LongerPath => dir1.len().cmp(&dir2.len()),
+                Rule::Protect(_) => Ordering::Equal,
+            };
+        }
+        order
+    }
+
+    /// The directory which the rules prefer is kept. The other one is redundant
+    /// only if all of its files are in the kept one and it isn't protected.
+    /// Ties are resolved in favour of `dir1`.
+    pub fn decide(&self, dir1: &str, dir2: &str, relation: Relation) -> Decision {
+        let (keep, other) = if self.compare(dir2, dir1) == Ordering::Greater {
+            (dir2, dir1)
+        } else {
+            (dir1, dir2)
+        };
+        let other_is_contained = match relation {
+            Relation::Identical => true,
+            Relation::Subset => other == dir1,
+            Relation::Superset => other == dir2,
+            Relation::Overlap => false,
+        };
+        Decision {
+            keep: String::from(keep),
+            redundant: if other_is_contained && !self.is_protected(other) {
+                Some(String::from(other))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(rules: &[&str]) -> Policy {
+        Policy {
+            rules: rules.iter().map(|rule| rule.parse().unwrap()).collect(),
+            // `old` holds files from 10 to 20, `new` from 30 to 40.
+            mtimes: HashMap::from([
+                (String::from("/old"), (10, 20)),
+                (String::from("/new"), (30, 40)),
+            ]),
+        }
+    }
+
+    #[test]
+    fn each_rule_prefers_its_directory() {
+        let cases = [
+            ("under:/backup", "/backup/a", "/home/a"),
+            ("older", "/old", "/new"),
+            ("newer", "/new", "/old"),
+            ("shorter-path", "/a", "/a/b"),
+            ("longer-path", "/a/b", "/a"),
+            ("protect:keep", "/keep/a", "/a"),
+        ];
+        for (rule, better, worse) in cases {
+            let policy = policy(&[rule]);
+            assert_eq!(policy.compare(better, worse), Ordering::Greater, "{}", rule);
+            assert_eq!(policy.compare(worse, better), Ordering::Less, "{}", rule);
+        }
+    }
+
+    #[test]
+    fn earlier_rules_win() {
+        let (short, long) = ("/a", "/a/b");
+        assert_eq!(
+            policy(&["shorter-path", "longer-path"]).compare(short, long),
+            Ordering::Greater
+        );
+        assert_eq!(
+            policy(&["longer-path", "shorter-path"]).compare(short, long),
+            Ordering::Less
+        );
+        // Protection wins over all rules.
+        assert_eq!(
+            policy(&["shorter-path", "protect:keep"]).compare("/a/keep", short),
+            Ordering::Greater
+        );
+        assert_eq!(policy(&[]).compare(short, long), Ordering::Equal);
+    }
+
+    #[test]
+    fn only_a_contained_directory_is_redundant() {
+        let policy = policy(&["shorter-path"]);
+        let decide = |dir1, dir2, relation| {
+            let decision = policy.decide(dir1, dir2, relation);
+            (decision.keep, decision.redundant)
+        };
+        let kept =
+            |keep: &str, redundant: Option<&str>| (String::from(keep), redundant.map(String::from));
+
+        assert_eq!(
+            decide("/a", "/a/b", Relation::Identical),
+            kept("/a", Some("/a/b"))
+        );
+        // dir1 is a subset of dir2: removing it loses nothing, removing dir2 would.
+        assert_eq!(
+            decide("/a/b", "/a", Relation::Subset),
+            kept("/a", Some("/a/b"))
+        );
+        assert_eq!(decide("/a", "/a/b", Relation::Subset), kept("/a", None));
+        assert_eq!(
+            decide("/a", "/a/b", Relation::Superset),
+            kept("/a", Some("/a/b"))
+        );
+        assert_eq!(decide("/a/b", "/a", Relation::Superset), kept("/a", None));
+        assert_eq!(decide("/a", "/a/b", Relation::Overlap), kept("/a", None));
+    }
+
+    #[test]
+    fn ties_keep_dir1_and_protected_dirs_are_never_redundant() {
+        let decision = policy(&[]).decide("/a", "/b", Relation::Identical);
+        assert_eq!(
+            (decision.keep.as_str(), decision.redundant.as_deref()),
+            ("/a", Some("/b"))
+        );
+
+        let policy = policy(&["protect:/a/", "protect:/b/"]);
+        let decision = policy.decide("/a", "/b", Relation::Identical);
+        assert_eq!((decision.keep.as_str(), decision.redundant), ("/a", None));
+    }
+}
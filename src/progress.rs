@@ -0,0 +1,147 @@
+use dirdups::{Event, Observer};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// An error reported during the run: its kind, path and message.
+struct LoggedError {
+    kind: &'static str,
+    path: String,
+    message: String,
+}
+
+/// Shows a progress bar for every stage of a scan, prints what actions do and
+/// file errors, and keeps the errors for the summary at the end of the run.
+pub struct ProgressBarObserver {
+    progress_bar: Mutex<Option<ProgressBar>>,
+    errors: Mutex<Vec<LoggedError>>,
+}
+
+impl ProgressBarObserver {
+    pub fn new() -> ProgressBarObserver {
+        ProgressBarObserver {
+            progress_bar: Mutex::new(None),
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Prints how many errors of each kind happened, nothing if none did.
+    pub fn print_summary(&self) {
+        let errors = self.errors.lock().unwrap();
+        if errors.is_empty() {
+            return;
+        }
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for error in errors.iter() {
+            *counts.entry(error.kind).or_default() += 1;
+        }
+        let counts: Vec<String> = counts
+            .iter()
+            .map(|(kind, count)| format!("{}: {}", kind, count))
+            .collect();
+        eprintln!("Errors: {}", counts.join(", "));
+    }
+
+    /// Writes all errors as CSV with kind, path and message columns.
+    pub fn write_errors(&self, path: &Path) -> io::Result<()> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["kind", "path", "message"])?;
+        for error in self.errors.lock().unwrap().iter() {
+            writer.write_record([error.kind, &error.path, &error.message])?;
+        }
+        writer.flush()
+    }
+
+    fn current(&self) -> Option<ProgressBar> {
+        self.progress_bar.lock().unwrap().clone()
+    }
+
+    fn print(&self, message: String) {
+        // A hidden progress bar would swallow the message.
+        match self.current() {
+            Some(progress_bar) if !progress_bar.is_hidden() => progress_bar.println(message),
+            _ => eprintln!("{}", message),
+        }
+    }
+}
+
+impl Observer for ProgressBarObserver {
+    fn event(&self, event: &Event) {
+        match event {
+            Event::WalkFinished { files } => eprintln!("Found: {} files", files),
+            Event::StageStarted { stage, files } => {
+                let progress_bar = ProgressBar::new(*files as u64);
+                progress_bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template("[{elapsed_precise}] {msg:10} {bar:80} {pos}/{len}"),
+                );
+                progress_bar.set_message(stage.to_string());
+                *self.progress_bar.lock().unwrap() = Some(progress_bar);
+            }
+            Event::FileHashed { .. } | Event::FileVerified { .. } => {
+                if let Some(progress_bar) = self.current() {
+                    progress_bar.inc(1);
+                }
+            }
+            Event::StageFinished { .. } => {
+                if let Some(progress_bar) = self.progress_bar.lock().unwrap().take() {
+                    progress_bar.finish();
+                }
+            }
+            Event::FileError { error } => {
+                self.errors.lock().unwrap().push(LoggedError {
+                    kind: error.kind(),
+                    path: error
+                        .path()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_default(),
+                    message: error.to_string(),
+                });
+                self.print(format!("Error: {}", error));
+            }
+            Event::SymlinkLoop { path, ancestor } => self.print(format!(
+                "Warning: {}: symlink loop to {}",
+                path.display(),
+                ancestor.display()
+            )),
+            Event::Skipped { path, reason } => {
+                eprintln!("Skipped: {}: {}", path.display(), reason)
+            }
+            Event::FileRemoved {
+                path,
+                copy,
+                dry_run: true,
+            } => println!(
+                "Would delete: {} (copy: {})",
+                path.display(),
+                copy.display()
+            ),
+            Event::DirCleared {
+                path,
+                removed: true,
+            } => println!("Deleted: {}", path.display()),
+            Event::DirCleared {
+                path,
+                removed: false,
+            } => println!("Deleted files of: {}", path.display()),
+            Event::FileLinked {
+                path,
+                source,
+                dry_run: true,
+            } => println!("Would link: {} -> {}", path.display(), source.display()),
+            Event::DirMoved {
+                path,
+                target,
+                dry_run,
+            } => println!(
+                "{}: {} -> {}",
+                if *dry_run { "Would move" } else { "Moved" },
+                path.display(),
+                target.display()
+            ),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,969 @@
+use crate::cache::Cache;
+use crate::chunks::{self, Chunk, NearDuplicate};
+use crate::duplicates::{self, Duplicate, DuplicateSet, RedundantDir};
+use crate::error::Error;
+use crate::filetype::{FileType, TypeFilter};
+use crate::hash::{Digest, HashAlgorithm};
+use crate::observer::{Event, Observer, Silent, Stage};
+use crate::perceptual::{self, PerceptualHash};
+use crate::policy::Policy;
+use crate::tree::DirTrees;
+use crate::walk::{self, FoundFile, WalkOptions};
+use humanize_rs::bytes::Bytes;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::hash::Hash;
+use std::io::{self, prelude::*, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+/// A scanned file.
+pub struct FileInfo {
+    pub path: String,
+    pub dir: String,
+    pub size: usize,
+    pub mtime: u128,
+    /// Device and inode are 0 where they are unknown.
+    pub device: u64,
+    pub inode: u64,
+    /// A symlink scanned as its own entry, its target path stands for the contents.
+    pub is_symlink: bool,
+    /// `path` with symlinks resolved, the key of the checksum cache.
+    pub(crate) real_path: String,
+    pub(crate) head_hash: Option<Digest>,
+    pub(crate) full_hash: Option<Digest>,
+    /// Id shared by files with exactly the same contents. It is the id of
+    /// their group unless a perceptual hash merged similar images into it.
+    pub(crate) content: u64,
+}
+
+/// Inclusive range of file sizes, written as `MIN..MAX`, `MIN..` or `..MAX`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SizeRange {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl SizeRange {
+    pub fn contains(&self, size: usize) -> bool {
+        size >= self.min && self.max.is_none_or(|max| size <= max)
+    }
+
+    /// The part of the range which is also in `other`.
+    pub fn intersect(&self, other: &SizeRange) -> SizeRange {
+        let max = match (self.max, other.max) {
+            (Some(max1), Some(max2)) => Some(max1.min(max2)),
+            (max1, max2) => max1.or(max2),
+        };
+        SizeRange {
+            min: self.min.max(other.min),
+            max,
+        }
+    }
+}
+
+/// Parses sizes like `100KB` or `4GB`.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    s.trim()
+        .parse::<Bytes>()
+        .map(|bytes| bytes.size())
+        .map_err(|_| format!("invalid size: {}", s))
+}
+
+impl FromStr for SizeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SizeRange, String> {
+        let (min, max) = s
+            .split_once("..")
+            .ok_or_else(|| format!("invalid size range: {}", s))?;
+        let range = SizeRange {
+            min: if min.is_empty() { 0 } else { parse_size(min)? },
+            max: if max.is_empty() {
+                None
+            } else {
+                Some(parse_size(max)?)
+            },
+        };
+        if range.max.is_some_and(|max| max < range.min) {
+            return Err(format!("empty size range: {}", s));
+        }
+        Ok(range)
+    }
+}
+
+/// Contents of a file, or the target path of a symlink scanned as its own entry.
+pub(crate) fn open_content(file: &FileInfo) -> io::Result<Box<dyn BufRead>> {
+    if file.is_symlink {
+        let target = fs::read_link(&file.path)?;
+        Ok(Box::new(io::Cursor::new(
+            target.into_os_string().into_encoded_bytes(),
+        )))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(&file.path)?)))
+    }
+}
+
+fn get_checksum(
+    file: &FileInfo,
+    read_first_bytes: usize,
+    algorithm: HashAlgorithm,
+) -> io::Result<Digest> {
+    let mut f = open_content(file)?;
+    let mut hasher = algorithm.hasher();
+    const BUF_SIZE: usize = 1024;
+    let mut buffer: [u8; BUF_SIZE] = [0; BUF_SIZE];
+
+    let mut bytes_readed = 0;
+    loop {
+        if read_first_bytes > 0 && bytes_readed >= read_first_bytes {
+            break;
+        }
+        let n = f.read(&mut buffer[..])?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[0..n]);
+        bytes_readed += n;
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(unix)]
+pub(crate) fn device_and_inode(metadata: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn device_and_inode(_metadata: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// With `symlinks_as_entries` a symlink isn't followed, it is described by its
+/// own metadata and the length of its target path.
+fn get_file_info(file: &FoundFile, symlinks_as_entries: bool) -> io::Result<FileInfo> {
+    let path = file.path.as_str();
+    let symlink_metadata = match symlinks_as_entries {
+        true => Some(fs::symlink_metadata(path)?).filter(Metadata::is_symlink),
+        false => None,
+    };
+    let is_symlink = symlink_metadata.is_some();
+    let (metadata, size) = match symlink_metadata {
+        Some(metadata) => (metadata, fs::read_link(path)?.as_os_str().len()),
+        None => {
+            let metadata = File::open(path)?.metadata()?;
+            let size = metadata.len() as usize;
+            (metadata, size)
+        }
+    };
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos());
+    let (device, inode) = device_and_inode(&metadata);
+    let dir: String = match Path::new(path).parent() {
+        Some(path) => String::from(path.to_string_lossy()),
+        None => String::from(""),
+    };
+    Ok(FileInfo {
+        path: String::from(path),
+        dir,
+        size,
+        mtime,
+        device,
+        inode,
+        is_symlink,
+        real_path: file.real_path.clone(),
+        head_hash: None,
+        full_hash: None,
+        content: 0,
+    })
+}
+
+/// Compares contents of two files byte by byte. An error comes with the file
+/// which couldn't be read.
+pub(crate) fn files_equal<'a>(
+    file1: &'a FileInfo,
+    file2: &'a FileInfo,
+) -> Result<bool, (&'a FileInfo, io::Error)> {
+    let mut f1 = open_content(file1).map_err(|e| (file1, e))?;
+    let mut f2 = open_content(file2).map_err(|e| (file2, e))?;
+    loop {
+        let buf1 = f1.fill_buf().map_err(|e| (file1, e))?;
+        let buf2 = f2.fill_buf().map_err(|e| (file2, e))?;
+        if buf1.is_empty() || buf2.is_empty() {
+            return Ok(buf1.is_empty() && buf2.is_empty());
+        }
+        let n = buf1.len().min(buf2.len());
+        if buf1[..n] != buf2[..n] {
+            return Ok(false);
+        }
+        f1.consume(n);
+        f2.consume(n);
+    }
+}
+
+pub(crate) fn file_error(observer: &dyn Observer, path: &str, error: io::Error) {
+    observer.event(&Event::FileError {
+        error: &Error::from_io(Path::new(path), error),
+    });
+}
+
+/// Splits every group of possibly identical files into smaller groups by `key`.
+/// Groups of a single file are already known to be unique and are passed through
+/// without reading them.
+fn refine_groups<K, F>(
+    groups: Vec<Vec<FileInfo>>,
+    stage: Stage,
+    observer: &dyn Observer,
+    key: F,
+) -> Vec<Vec<FileInfo>>
+where
+    K: Eq + Hash + Send,
+    F: Fn(&mut FileInfo) -> io::Result<K> + Sync,
+{
+    let files_cnt = groups.iter().filter(|g| g.len() > 1).map(Vec::len).sum();
+    observer.event(&Event::StageStarted {
+        stage,
+        files: files_cnt,
+    });
+
+    let refined = groups
+        .into_par_iter()
+        .flat_map_iter(|group| {
+            if group.len() < 2 {
+                return vec![group];
+            }
+            let keys: Vec<(K, FileInfo)> = group
+                .into_par_iter()
+                .filter_map(|mut file| {
+                    let k = key(&mut file);
+                    observer.event(&Event::FileHashed {
+                        stage,
+                        path: Path::new(&file.path),
+                    });
+                    match k {
+                        Ok(k) => Some((k, file)),
+                        Err(e) => {
+                            file_error(observer, &file.path, e);
+                            None
+                        }
+                    }
+                })
+                .collect();
+            let mut subgroups: HashMap<K, Vec<FileInfo>> = HashMap::new();
+            for (k, file) in keys {
+                subgroups.entry(k).or_default().push(file);
+            }
+            subgroups.into_values().collect()
+        })
+        .collect();
+    observer.event(&Event::StageFinished { stage });
+    refined
+}
+
+/// Splits groups of files with equal checksums by comparing their contents byte by byte.
+fn verify_groups(groups: Vec<Vec<FileInfo>>, observer: &dyn Observer) -> Vec<Vec<FileInfo>> {
+    let files_cnt = groups.iter().filter(|g| g.len() > 1).map(Vec::len).sum();
+    observer.event(&Event::StageStarted {
+        stage: Stage::Verify,
+        files: files_cnt,
+    });
+
+    let verified = groups
+        .into_par_iter()
+        .flat_map_iter(|group| {
+            if group.len() < 2 {
+                return vec![group];
+            }
+            let mut subgroups: Vec<Vec<FileInfo>> = Vec::new();
+            'files: for file in group {
+                observer.event(&Event::FileVerified {
+                    path: Path::new(&file.path),
+                });
+                let mut i = 0;
+                while i < subgroups.len() {
+                    let equal = files_equal(&subgroups[i][0], &file)
+                        .map_err(|(failed, e)| (std::ptr::eq(failed, &file), e));
+                    match equal {
+                        Ok(true) => {
+                            subgroups[i].push(file);
+                            continue 'files;
+                        }
+                        Ok(false) => i += 1,
+                        Err((true, e)) => {
+                            file_error(observer, &file.path, e);
+                            continue 'files;
+                        }
+                        // The first file of the subgroup became unreadable,
+                        // the next one is compared instead.
+                        Err((false, e)) => {
+                            let unreadable = subgroups[i].remove(0);
+                            file_error(observer, &unreadable.path, e);
+                            if subgroups[i].is_empty() {
+                                subgroups.remove(i);
+                            }
+                        }
+                    }
+                }
+                subgroups.push(vec![file]);
+            }
+            subgroups
+        })
+        .collect();
+    observer.event(&Event::StageFinished {
+        stage: Stage::Verify,
+    });
+    verified
+}
+
+/// Groups files with identical contents. Files are first grouped by size, then
+/// by a `algorithm` checksum of their first `head` bytes, then by a checksum of the whole
+/// file and, if `verify` is set, by comparing them byte by byte. Every stage
+/// only reads files which are still indistinguishable after the previous one.
+fn group_identical_files(
+    files: Vec<FileInfo>,
+    head: usize,
+    algorithm: HashAlgorithm,
+    verify: bool,
+    observer: &dyn Observer,
+) -> Vec<Vec<FileInfo>> {
+    // Only the first path of an inode is read, its other hardlinks are put
+    // into the same group at the end.
+    let mut inodes: HashSet<(u64, u64)> = HashSet::new();
+    let mut hardlinks: HashMap<(u64, u64), Vec<FileInfo>> = HashMap::new();
+    let mut by_size: HashMap<usize, Vec<FileInfo>> = HashMap::new();
+    for file in files {
+        let key = (file.device, file.inode);
+        if file.inode != 0 && !inodes.insert(key) {
+            hardlinks.entry(key).or_default().push(file);
+            continue;
+        }
+        by_size.entry(file.size).or_default().push(file);
+    }
+    let mut groups: Vec<Vec<FileInfo>> = by_size.into_values().collect();
+
+    if head > 0 {
+        groups = refine_groups(groups, Stage::Head, observer, |file| {
+            if file.head_hash.is_none() {
+                file.head_hash = Some(get_checksum(file, head, algorithm)?);
+            }
+            Ok(file.head_hash)
+        });
+    }
+    groups = refine_groups(groups, Stage::Full, observer, |file| {
+        if file.full_hash.is_some() {
+            // Already known from the cache.
+        } else if head > 0 && file.size <= head {
+            // The head checksum already covered the whole file.
+            file.full_hash = file.head_hash;
+        } else {
+            file.full_hash = Some(get_checksum(file, 0, algorithm)?);
+        }
+        Ok(file.full_hash)
+    });
+    if verify {
+        groups = verify_groups(groups, observer);
+    }
+
+    for group in groups.iter_mut() {
+        let mut links: Vec<FileInfo> = Vec::new();
+        for file in group.iter() {
+            if let Some(mut same_inode) = hardlinks.remove(&(file.device, file.inode)) {
+                for link in same_inode.iter_mut() {
+                    link.head_hash = file.head_hash;
+                    link.full_hash = file.full_hash;
+                }
+                links.append(&mut same_inode);
+            }
+        }
+        group.append(&mut links);
+    }
+    groups
+}
+
+/// Reads metadata of `files`, skipping the ones outside of all `sizes` and
+/// the ones rejected by `filter`.
+fn filter_files(
+    files: &[FoundFile],
+    sizes: &[SizeRange],
+    filter: &TypeFilter,
+    symlinks_as_entries: bool,
+    observer: &dyn Observer,
+) -> Vec<FileInfo> {
+    files
+        .par_iter()
+        .filter(|file| filter.matches_extension(&file.path))
+        .filter_map(|file| {
+            let file_info = match get_file_info(file, symlinks_as_entries) {
+                Ok(file_info) => file_info,
+                Err(e) => {
+                    file_error(observer, &file.path, e);
+                    return None;
+                }
+            };
+            if !sizes.iter().any(|range| range.contains(file_info.size)) {
+                return None;
+            }
+            match filter.matches_content(&file_info) {
+                Ok(true) => Some(file_info),
+                Ok(false) => None,
+                Err(e) => {
+                    file_error(observer, &file.path, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn load_files_info(
+    mut files_info: Vec<FileInfo>,
+    head: usize,
+    algorithm: HashAlgorithm,
+    verify: bool,
+    mut cache: Option<&mut Cache>,
+    observer: &dyn Observer,
+) -> Vec<Vec<FileInfo>> {
+    if let Some(cache) = &cache {
+        for file in files_info.iter_mut() {
+            cache.lookup(file, head, algorithm);
+        }
+    }
+
+    let groups = group_identical_files(files_info, head, algorithm, verify, observer);
+
+    if let Some(cache) = &mut cache {
+        for file in groups.iter().flatten() {
+            cache.update(file, head, algorithm);
+        }
+    }
+    groups
+}
+
+fn index_files(
+    groups: &[Vec<FileInfo>],
+    hash_dirs: &mut HashMap<u64, HashSet<String>>,
+    dir_hashes: &mut HashMap<String, HashSet<u64>>,
+) {
+    // Every group of identical files gets its own id which is used as a file identity.
+    for (hash, group) in groups.iter().enumerate() {
+        let hash = hash as u64;
+        for file in group {
+            hash_dirs.entry(hash).or_default().insert(file.dir.clone());
+            dir_hashes.entry(file.dir.clone()).or_default().insert(hash);
+        }
+    }
+}
+
+/// Settings of a scan, built by chaining its methods:
+///
+/// ```no_run
+/// let result = dirdups::Scanner::new(["photos", "backup"])
+///     .min_size(1024)
+///     .exclude(".git")
+///     .scan()?;
+/// for duplicate in result.duplicates(10) {
+///     println!("{} - {}", duplicate.dir1, duplicate.dir2);
+/// }
+/// # Ok::<(), dirdups::Error>(())
+/// ```
+pub struct Scanner {
+    directories: Vec<String>,
+    min_size: usize,
+    max_size: Option<usize>,
+    size_ranges: Vec<SizeRange>,
+    extensions: Vec<String>,
+    file_types: Vec<FileType>,
+    walk: WalkOptions,
+    head: usize,
+    algorithm: HashAlgorithm,
+    verify: bool,
+    cache: bool,
+    chunk_size: usize,
+    perceptual: Option<PerceptualHash>,
+    max_distance: u32,
+    jobs: usize,
+    observer: Option<Box<dyn Observer>>,
+}
+
+impl Scanner {
+    /// By default all files of at least 1 byte are scanned, their first 1024
+    /// bytes are compared by an xxh3 checksum before whole files are, and the
+    /// checksum cache is not used.
+    pub fn new<I, S>(directories: I) -> Scanner
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Scanner {
+            directories: directories.into_iter().map(Into::into).collect(),
+            min_size: 1,
+            max_size: None,
+            size_ranges: Vec::new(),
+            extensions: Vec::new(),
+            file_types: Vec::new(),
+            walk: WalkOptions {
+                exclude: Vec::new(),
+                include: Vec::new(),
+                gitignore: false,
+                skip_hidden: false,
+                follow_symlinks: false,
+                symlinks_as_entries: false,
+            },
+            head: 1024,
+            algorithm: HashAlgorithm::Xxh3,
+            verify: false,
+            cache: false,
+            chunk_size: 0,
+            perceptual: None,
+            max_distance: 8,
+            jobs: 0,
+            observer: None,
+        }
+    }
+
+    pub fn min_size(&mut self, size: usize) -> &mut Scanner {
+        self.min_size = size;
+        self
+    }
+
+    pub fn max_size(&mut self, size: usize) -> &mut Scanner {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Scan only files in one of the added ranges, narrowed down by `min_size`
+    /// and `max_size`.
+    pub fn size_range(&mut self, range: SizeRange) -> &mut Scanner {
+        self.size_ranges.push(range);
+        self
+    }
+
+    /// Skip files and directories matching `glob`, directories with all their contents.
+    pub fn exclude(&mut self, glob: &str) -> &mut Scanner {
+        self.walk.exclude.push(String::from(glob));
+        self
+    }
+
+    /// Scan only files matching one of the included globs.
+    pub fn include(&mut self, glob: &str) -> &mut Scanner {
+        self.walk.include.push(String::from(glob));
+        self
+    }
+
+    /// Scan only files with one of the added extensions.
+    pub fn extension(&mut self, extension: &str) -> &mut Scanner {
+        self.extensions.push(String::from(extension));
+        self
+    }
+
+    /// Scan only files of one of the added types.
+    pub fn file_type(&mut self, file_type: FileType) -> &mut Scanner {
+        self.file_types.push(file_type);
+        self
+    }
+
+    pub fn gitignore(&mut self, gitignore: bool) -> &mut Scanner {
+        self.walk.gitignore = gitignore;
+        self
+    }
+
+    pub fn skip_hidden(&mut self, skip_hidden: bool) -> &mut Scanner {
+        self.walk.skip_hidden = skip_hidden;
+        self
+    }
+
+    pub fn follow_symlinks(&mut self, follow_symlinks: bool) -> &mut Scanner {
+        self.walk.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    pub fn symlinks_as_entries(&mut self, symlinks_as_entries: bool) -> &mut Scanner {
+        self.walk.symlinks_as_entries = symlinks_as_entries;
+        self
+    }
+
+    /// How many first bytes are compared before whole files, 0 to compare whole files only.
+    pub fn head(&mut self, head: usize) -> &mut Scanner {
+        self.head = head;
+        self
+    }
+
+    pub fn hash(&mut self, algorithm: HashAlgorithm) -> &mut Scanner {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Compare files byte by byte after their checksums match.
+    pub fn verify(&mut self, verify: bool) -> &mut Scanner {
+        self.verify = verify;
+        self
+    }
+
+    /// Read and update the checksum cache in the user cache directory.
+    pub fn cache(&mut self, cache: bool) -> &mut Scanner {
+        self.cache = cache;
+        self
+    }
+
+    /// Splits every distinct content into chunks of about this many bytes for
+    /// `ScanResult::near_duplicates`, 0 to skip it. It reads all files whole.
+    pub fn chunk_size(&mut self, chunk_size: usize) -> &mut Scanner {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Treats images whose perceptual hashes are close as the same file, so
+    /// they count toward the files two directories have in common. Images are
+    /// recognized by their extension and read whole.
+    pub fn perceptual(&mut self, kind: PerceptualHash) -> &mut Scanner {
+        self.perceptual = Some(kind);
+        self
+    }
+
+    /// How many of the 64 bits of perceptual hashes of two images may differ
+    /// for them to count as the same, 8 by default.
+    pub fn max_distance(&mut self, max_distance: u32) -> &mut Scanner {
+        self.max_distance = max_distance;
+        self
+    }
+
+    /// Number of threads reading files, 0 for one thread per CPU.
+    pub fn jobs(&mut self, jobs: usize) -> &mut Scanner {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Receives events of the scan, nothing is printed by the scan itself.
+    pub fn observer(&mut self, observer: impl Observer + 'static) -> &mut Scanner {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Every `--size` range narrowed down by the minimal and maximal size.
+    fn sizes(&self) -> Vec<SizeRange> {
+        let limits = SizeRange {
+            min: self.min_size,
+            max: self.max_size,
+        };
+        if self.size_ranges.is_empty() {
+            vec![limits]
+        } else {
+            self.size_ranges
+                .iter()
+                .map(|range| range.intersect(&limits))
+                .collect()
+        }
+    }
+
+    /// Walks the directories and groups their files by contents. Fails when
+    /// one of the directories can't be read, errors of single files are only
+    /// reported to the observer.
+    pub fn scan(&self) -> Result<ScanResult, Error> {
+        if self.chunk_size > 0 {
+            chunks::check_chunk_size(self.chunk_size).map_err(Error::InvalidOption)?;
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs)
+            .build()
+            .map_err(|e| {
+                Error::InvalidOption(format!("can't start {} threads: {}", self.jobs, e))
+            })?;
+        pool.install(|| self.scan_files())
+    }
+
+    fn scan_files(&self) -> Result<ScanResult, Error> {
+        let observer = self.observer.as_deref().unwrap_or(&Silent);
+        let mut cache = match self.cache {
+            true => Cache::default_path().map(Cache::load),
+            false => None,
+        };
+
+        observer.event(&Event::WalkStarted {
+            directories: &self.directories,
+        });
+        let files = walk::get_files(&self.directories, &self.walk, observer)?;
+        observer.event(&Event::WalkFinished { files: files.len() });
+
+        let files_info = filter_files(
+            &files,
+            &self.sizes(),
+            &TypeFilter::new(&self.extensions, &self.file_types),
+            self.walk.symlinks_as_entries,
+            observer,
+        );
+        let mut groups = load_files_info(
+            files_info,
+            self.head,
+            self.algorithm,
+            self.verify,
+            cache.as_mut(),
+            observer,
+        );
+        for (id, group) in groups.iter_mut().enumerate() {
+            for file in group.iter_mut() {
+                file.content = id as u64;
+            }
+        }
+
+        if let Some(cache) = &mut cache {
+            // Directories which can't be resolved were reported by the walk.
+            let real_dirs: Vec<PathBuf> = self
+                .directories
+                .iter()
+                .filter_map(|dir| fs::canonicalize(dir).ok())
+                .collect();
+            cache.retain_seen(&real_dirs, &files);
+            if let Err(e) = cache.save() {
+                observer.event(&Event::FileError {
+                    error: &Error::from_io(cache.path(), e),
+                });
+            }
+        }
+
+        let (groups, similar) = match self.perceptual {
+            Some(kind) => {
+                perceptual::merge_similar_images(groups, kind, self.max_distance, observer)
+            }
+            None => (groups, HashSet::new()),
+        };
+
+        let group_chunks = match self.chunk_size {
+            0 => Vec::new(),
+            chunk_size => chunks::chunk_groups(&groups, chunk_size, observer),
+        };
+
+        let mut result = ScanResult {
+            directories: self.directories.clone(),
+            groups,
+            similar,
+            group_chunks,
+            hash_dirs: HashMap::new(),
+            dir_hashes: HashMap::new(),
+        };
+        index_files(
+            &result.groups,
+            &mut result.hash_dirs,
+            &mut result.dir_hashes,
+        );
+        if self.observer.is_some() {
+            for set in result.duplicate_sets() {
+                observer.event(&Event::DuplicateFound { set: &set });
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Scanned files grouped by contents, and an index of which directories hold
+/// which contents.
+pub struct ScanResult {
+    directories: Vec<String>,
+    /// Groups of identical files, the index of a group is used as a file id.
+    /// With a perceptual hash, a group may hold similar images, the biggest first.
+    pub(crate) groups: Vec<Vec<FileInfo>>,
+    /// Ids of groups holding similar images besides identical ones, which are
+    /// told apart by `FileInfo::content`.
+    pub(crate) similar: HashSet<u64>,
+    /// Chunks of every group, empty unless the scan split files into chunks.
+    pub(crate) group_chunks: Vec<Vec<Chunk>>,
+    pub(crate) hash_dirs: HashMap<u64, HashSet<String>>,
+    pub(crate) dir_hashes: HashMap<String, HashSet<u64>>,
+}
+
+impl ScanResult {
+    /// The scanned directories.
+    pub fn directories(&self) -> &[String] {
+        &self.directories
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &FileInfo> {
+        self.groups.iter().flatten()
+    }
+
+    /// Sets of two or more identical files, the biggest first.
+    pub fn duplicate_sets(&self) -> Vec<DuplicateSet> {
+        duplicates::find_duplicate_sets(self)
+    }
+
+    /// Pairs of directories sharing at least `min_intersection` files, sorted by their paths.
+    pub fn duplicates(&self, min_intersection: usize) -> Vec<Duplicate> {
+        duplicates::find_duplicates(self, min_intersection)
+    }
+
+    /// Directories all files of which are also found in other directories.
+    pub fn redundant_dirs(&self, policy: &Policy) -> Vec<RedundantDir> {
+        duplicates::find_redundant_dirs(self, policy)
+    }
+
+    /// Pairs of directories whose files share at least `min_containment` of
+    /// the contents of the smaller one, compared by content-defined chunks.
+    /// Empty unless `Scanner::chunk_size` was set.
+    pub fn near_duplicates(&self, min_containment: f64) -> Vec<NearDuplicate> {
+        if self.group_chunks.is_empty() {
+            return Vec::new();
+        }
+        chunks::find_near_duplicates(self, min_containment)
+    }
+
+    /// Content ids of whole directory trees below the scanned directories.
+    pub fn dir_trees(&self) -> DirTrees {
+        DirTrees::build(&self.groups, &self.directories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dirdups-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_info(dir: &Path, name: &str, contents: &[u8]) -> FileInfo {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        let path = String::from(path.to_string_lossy());
+        let found = FoundFile {
+            real_path: path.clone(),
+            path,
+        };
+        get_file_info(&found, false).unwrap()
+    }
+
+    /// Names of grouped files, sorted so the result doesn't depend on hashing.
+    fn names(groups: &[Vec<FileInfo>]) -> Vec<Vec<String>> {
+        let mut names: Vec<Vec<String>> = groups
+            .iter()
+            .map(|group| {
+                let mut names: Vec<String> = group.iter().map(|f| file_name(&f.path)).collect();
+                names.sort();
+                names
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn file_name(path: &str) -> String {
+        String::from(Path::new(path).file_name().unwrap().to_string_lossy())
+    }
+
+    fn range(min: usize, max: Option<usize>) -> SizeRange {
+        SizeRange { min, max }
+    }
+
+    #[test]
+    fn size_ranges_are_parsed() {
+        assert_eq!("1KB..2KiB".parse(), Ok(range(1000, Some(2048))));
+        assert_eq!("100..".parse(), Ok(range(100, None)));
+        assert_eq!("..1MB".parse(), Ok(range(0, Some(1_000_000))));
+        assert_eq!("5..5".parse(), Ok(range(5, Some(5))));
+        for invalid in ["100", "2KB..1KB", "x..", "..y"] {
+            assert!(invalid.parse::<SizeRange>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn size_ranges_intersect() {
+        let open = range(100, None);
+        assert_eq!(open.intersect(&range(0, Some(500))), range(100, Some(500)));
+        assert_eq!(open.intersect(&range(200, None)), range(200, None));
+        assert_eq!(
+            range(0, Some(300)).intersect(&range(10, Some(200))),
+            range(10, Some(200))
+        );
+        // Disjoint ranges give a range containing nothing.
+        let empty = range(0, Some(10)).intersect(&range(20, None));
+        assert!(!(0..100).any(|size| empty.contains(size)));
+        assert!(open.contains(100) && !open.contains(99));
+    }
+
+    #[test]
+    fn equal_heads_are_split_by_full_hash() {
+        let dir = temp_dir("heads");
+        let contents = vec![b'x'; 2048];
+        let mut different_end = contents.clone();
+        different_end[2000] = b'y';
+        let files = vec![
+            file_info(&dir, "a", &contents),
+            file_info(&dir, "b", &different_end),
+            file_info(&dir, "c", &contents),
+        ];
+        let groups = group_identical_files(files, 1024, HashAlgorithm::Xxh3, false, &Silent);
+        assert_eq!(names(&groups), [vec!["a", "c"], vec!["b"]]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn full_hash_collisions_are_split_by_verify() {
+        let dir = temp_dir("collisions");
+        // Equal checksums of different contents, as if they collided.
+        let files: Vec<FileInfo> = [("a", b"first"), ("b", b"other"), ("c", b"first")]
+            .iter()
+            .map(|(name, contents)| {
+                let mut file = file_info(&dir, name, *contents);
+                file.full_hash = Some(Digest::default());
+                file
+            })
+            .collect();
+        let groups = group_identical_files(files, 0, HashAlgorithm::Xxh3, true, &Silent);
+        assert_eq!(names(&groups), [vec!["a", "c"], vec!["b"]]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Collects paths of files which couldn't be read.
+    #[derive(Default)]
+    struct Errors(std::sync::Mutex<Vec<String>>);
+
+    impl Observer for Errors {
+        fn event(&self, event: &Event) {
+            if let Event::FileError { error } = event {
+                let path = error.path().unwrap().to_string_lossy();
+                self.0.lock().unwrap().push(file_name(&path));
+            }
+        }
+    }
+
+    #[test]
+    fn unreadable_files_are_left_out_of_verified_groups() {
+        let dir = temp_dir("verify");
+        let files: Vec<FileInfo> = ["a", "b", "c"]
+            .iter()
+            .map(|name| file_info(&dir, name, b"same"))
+            .collect();
+        // The first file of the group vanishes after it was hashed.
+        fs::remove_file(dir.join("a")).unwrap();
+        let errors = Errors::default();
+        let groups = verify_groups(vec![files], &errors);
+        assert_eq!(names(&groups), [vec!["b", "c"]]);
+        assert_eq!(*errors.0.lock().unwrap(), ["a"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unique_files_are_not_read_further() {
+        let dir = temp_dir("unique");
+        let files = vec![
+            file_info(&dir, "a", &[b'a'; 2048]),
+            file_info(&dir, "b", &[b'b'; 2048]),
+            file_info(&dir, "c", b"short"),
+        ];
+        let groups = group_identical_files(files, 1024, HashAlgorithm::Xxh3, true, &Silent);
+        assert_eq!(names(&groups), [vec!["a"], vec!["b"], vec!["c"]]);
+        for file in groups.iter().flatten() {
+            // A unique size needs no checksum, a unique head no full one.
+            assert_eq!(file.head_hash.is_some(), file.size == 2048);
+            assert!(file.full_hash.is_none());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
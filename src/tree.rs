@@ -0,0 +1,245 @@
+use crate::scan::FileInfo;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+#[derive(Serialize)]
+pub struct DuplicateTree {
+    pub dirs: Vec<String>,
+    pub files_number: usize,
+    pub size: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Entry {
+    File(String, u64),
+    Dir(String, u64),
+}
+
+struct TreeInfo {
+    files_number: usize,
+    size: usize,
+}
+
+/// Content ids of all directories below the searched ones. A directory id is
+/// derived from the names and ids of its files and subdirectories, Merkle-style,
+/// so two directories get the same id only if their whole trees are identical.
+/// Ids are interned rather than hashed, so unlike checksums they never collide.
+pub struct DirTrees {
+    roots: Vec<String>,
+    dir_ids: HashMap<String, u64>,
+    trees: Vec<TreeInfo>,
+}
+
+fn entry_name(path: &str) -> String {
+    match Path::new(path).file_name() {
+        Some(name) => String::from(name.to_string_lossy()),
+        None => String::from(path),
+    }
+}
+
+fn parent_dir(dir: &str) -> Option<String> {
+    Path::new(dir)
+        .parent()
+        .map(|path| String::from(path.to_string_lossy()))
+}
+
+impl DirTrees {
+    /// Files of `groups` are told apart by their `content` id, so images merged
+    /// by a perceptual hash make different trees.
+    pub(crate) fn build(groups: &[Vec<FileInfo>], roots: &[String]) -> DirTrees {
+        let mut dir_trees = DirTrees {
+            roots: roots.to_vec(),
+            dir_ids: HashMap::new(),
+            trees: Vec::new(),
+        };
+
+        let mut entries: HashMap<String, Vec<Entry>> = HashMap::new();
+        let mut file_sizes: HashMap<String, usize> = HashMap::new();
+        for file in groups.iter().flatten() {
+            entries
+                .entry(file.dir.clone())
+                .or_default()
+                .push(Entry::File(entry_name(&file.path), file.content));
+            *file_sizes.entry(file.dir.clone()).or_default() += file.size;
+        }
+
+        // Register every directory in its parent up to the searched directory.
+        let mut dirs: Vec<String> = entries.keys().cloned().collect();
+        let mut registered: HashSet<String> = dirs.iter().cloned().collect();
+        let mut i = 0;
+        while i < dirs.len() {
+            let dir = dirs[i].clone();
+            i += 1;
+            if dir_trees.is_root(&dir) {
+                continue;
+            }
+            if let Some(parent) = parent_dir(&dir) {
+                if registered.insert(parent.clone()) {
+                    dirs.push(parent.clone());
+                }
+                entries.entry(parent).or_default();
+            }
+        }
+
+        // Deeper directories first, so subdirectory ids are known before their parents.
+        dirs.sort_by_key(|dir| std::cmp::Reverse(Path::new(dir).components().count()));
+
+        let mut interned: HashMap<Vec<Entry>, u64> = HashMap::new();
+        let mut children: HashMap<String, Vec<Entry>> = HashMap::new();
+        for dir in dirs {
+            let mut key = entries.remove(&dir).unwrap_or_default();
+            key.extend(children.remove(&dir).unwrap_or_default());
+            key.sort();
+
+            let mut files_number = 0;
+            let mut size = file_sizes.get(&dir).copied().unwrap_or(0);
+            for entry in key.iter() {
+                match entry {
+                    Entry::File(_, _) => files_number += 1,
+                    Entry::Dir(_, id) => {
+                        let subtree = &dir_trees.trees[*id as usize];
+                        files_number += subtree.files_number;
+                        size += subtree.size;
+                    }
+                }
+            }
+
+            let next_id = interned.len() as u64;
+            let id = *interned.entry(key).or_insert(next_id);
+            if id == next_id {
+                dir_trees.trees.push(TreeInfo { files_number, size });
+            }
+
+            if !dir_trees.is_root(&dir) {
+                if let Some(parent) = parent_dir(&dir) {
+                    children
+                        .entry(parent)
+                        .or_default()
+                        .push(Entry::Dir(entry_name(&dir), id));
+                }
+            }
+            dir_trees.dir_ids.insert(dir, id);
+        }
+        dir_trees
+    }
+
+    fn is_root(&self, dir: &str) -> bool {
+        self.roots
+            .iter()
+            .any(|root| Path::new(root) == Path::new(dir))
+    }
+
+    /// Whether both directories contain identical trees.
+    pub fn same_tree(&self, dir1: &str, dir2: &str) -> bool {
+        match (self.dir_ids.get(dir1), self.dir_ids.get(dir2)) {
+            (Some(id1), Some(id2)) => id1 == id2,
+            _ => false,
+        }
+    }
+
+    /// Groups of identical directory trees. A group is omitted when the parents
+    /// of its directories are different directories with identical trees too,
+    /// since the group of the parents already implies it.
+    pub fn duplicates(&self) -> Vec<DuplicateTree> {
+        let mut id_dirs: HashMap<u64, Vec<&String>> = HashMap::new();
+        for (dir, id) in self.dir_ids.iter() {
+            id_dirs.entry(*id).or_default().push(dir);
+        }
+
+        let mut duplicates = Vec::new();
+        for (id, dirs) in id_dirs.iter() {
+            if dirs.len() < 2 {
+                continue;
+            }
+            let parents: Option<HashSet<String>> = dirs
+                .iter()
+                .map(|dir| match self.is_root(dir) {
+                    true => None,
+                    false => parent_dir(dir),
+                })
+                .collect();
+            let covered = parents.is_some_and(|parents| {
+                let mut parent_ids = parents.iter().map(|parent| self.dir_ids.get(parent));
+                let first = parent_ids.next().flatten();
+                parents.len() == dirs.len()
+                    && first.is_some()
+                    && parent_ids.all(|parent_id| parent_id == first)
+            });
+            if covered {
+                continue;
+            }
+
+            let mut dirs: Vec<String> = dirs.iter().map(|dir| dir.to_string()).collect();
+            dirs.sort();
+            let tree = &self.trees[*id as usize];
+            duplicates.push(DuplicateTree {
+                dirs,
+                files_number: tree.files_number,
+                size: tree.size,
+            });
+        }
+        duplicates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Scanner;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Creates `files` with the same contents under a new temporary directory.
+    fn create(name: &str, files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("dirdups-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "same").unwrap();
+        }
+        root
+    }
+
+    /// Reported groups with paths relative to `root`.
+    fn duplicate_trees(root: &Path) -> Vec<Vec<String>> {
+        let result = Scanner::new([root.to_string_lossy()]).scan().unwrap();
+        let mut groups: Vec<Vec<String>> = result
+            .dir_trees()
+            .duplicates()
+            .into_iter()
+            .map(|tree| {
+                tree.dirs
+                    .iter()
+                    .map(|dir| {
+                        let dir = Path::new(dir).strip_prefix(root).unwrap();
+                        String::from(dir.to_string_lossy())
+                    })
+                    .collect()
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    #[test]
+    fn subdirectories_of_identical_trees_are_omitted() {
+        let root = create("trees", &["A/2019/x", "B/2019/x"]);
+        assert_eq!(duplicate_trees(&root), [vec!["A", "B"]]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn trees_inside_different_trees_are_kept() {
+        let root = create("trees-apart", &["A/x/f", "B/y/f", "C/x/f", "D/y/f"]);
+        assert_eq!(
+            duplicate_trees(&root),
+            [
+                vec!["A", "C"],
+                vec!["A/x", "B/y", "C/x", "D/y"],
+                vec!["B", "D"],
+            ]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,638 @@
+use dirdups::actions::{self, Removal, Summary};
+use dirdups::policy::Policy;
+use dirdups::{Duplicate, Error, Observer, Relation, ScanResult};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Action {
+    Keep,
+    Delete,
+    Move(PathBuf),
+}
+
+impl Action {
+    fn label(&self) -> &'static str {
+        match self {
+            Action::Keep => "[keep] ",
+            Action::Delete => "[delete] ",
+            Action::Move(_) => "[move] ",
+        }
+    }
+
+    fn removes_dir(&self) -> bool {
+        !matches!(self, Action::Keep)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Dir1,
+    Dir2,
+}
+
+enum Mode {
+    Normal,
+    MoveTarget(String),
+    Confirm,
+}
+
+/// Contents of both directories of the selected pair.
+struct Details {
+    only_in_dir1: Vec<String>,
+    shared: Vec<String>,
+    only_in_dir2: Vec<String>,
+    scroll: u16,
+}
+
+struct App<'a> {
+    duplicates: &'a [Duplicate],
+    policy: &'a Policy,
+    list_state: ListState,
+    side: Side,
+    details: Option<Details>,
+    mode: Mode,
+    actions: BTreeMap<String, Action>,
+    /// The other directory of the pair each directory was marked in, which
+    /// holds copies of its files.
+    copies_in: HashMap<String, String>,
+    status: String,
+}
+
+const HELP: &str =
+    "↑↓ select  Tab side  Enter files  d delete  m move  k keep  u unmark  a apply  q quit";
+
+fn is_inside(path: &str, dir: &str) -> bool {
+    Path::new(path).starts_with(dir)
+}
+
+fn file_name(path: &str) -> String {
+    match Path::new(path).file_name() {
+        Some(name) => String::from(name.to_string_lossy()),
+        None => String::from(path),
+    }
+}
+
+/// Names of files directly in `dir` which are not in `shared`.
+fn unique_files(dir: &str, shared: &HashSet<&String>) -> Vec<String> {
+    let mut files: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|entry| String::from(entry.path().to_string_lossy()))
+            .filter(|path| !shared.contains(path))
+            .map(|path| file_name(&path))
+            .collect(),
+        Err(e) => vec![format!("Error: {}", e)],
+    };
+    files.sort();
+    files
+}
+
+fn load_details(duplicate: &Duplicate) -> Details {
+    let shared1: HashSet<&String> = duplicate
+        .shared_files
+        .iter()
+        .flat_map(|file| file.dir1_files.iter())
+        .collect();
+    let shared2: HashSet<&String> = duplicate
+        .shared_files
+        .iter()
+        .flat_map(|file| file.dir2_files.iter())
+        .collect();
+    let names = |files: &[String]| {
+        files
+            .iter()
+            .map(|path| file_name(path))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let shared = duplicate
+        .shared_files
+        .iter()
+        .map(|file| {
+            let names1 = names(&file.dir1_files);
+            let names2 = names(&file.dir2_files);
+            if file.similar {
+                format!("{} ~ {}", names1, names2)
+            } else if names1 == names2 {
+                names1
+            } else {
+                format!("{} = {}", names1, names2)
+            }
+        })
+        .collect();
+    Details {
+        only_in_dir1: unique_files(&duplicate.dir1, &shared1),
+        shared,
+        only_in_dir2: unique_files(&duplicate.dir2, &shared2),
+        scroll: 0,
+    }
+}
+
+impl<'a> App<'a> {
+    fn selected(&self) -> Option<&'a Duplicate> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.duplicates.get(i))
+    }
+
+    fn selected_dirs(&self) -> Option<(&'a String, &'a String)> {
+        let duplicate = self.selected()?;
+        Some(match self.side {
+            Side::Dir1 => (&duplicate.dir1, &duplicate.dir2),
+            Side::Dir2 => (&duplicate.dir2, &duplicate.dir1),
+        })
+    }
+
+    /// Marks the selected side of the selected pair. The other side must stay
+    /// in place, otherwise the files of the pair would be lost, and a side can
+    /// be deleted only when all of its files are in the other one. Directories
+    /// already marked must not hold the copies of the new one, nor be inside it
+    /// or contain it, nor have their copies in it.
+    fn mark(&mut self, action: Action) {
+        let (duplicate, (dir, other)) = match (self.selected(), self.selected_dirs()) {
+            (Some(duplicate), Some(dirs)) => (duplicate, dirs),
+            _ => return,
+        };
+        if action.removes_dir() {
+            if let Err(reason) = self.check_removal(duplicate, dir, other, &action) {
+                self.status = reason;
+                return;
+            }
+            self.copies_in.insert(dir.clone(), other.clone());
+        } else {
+            self.copies_in.remove(dir);
+        }
+        self.status = format!("{}{}", action.label(), dir);
+        self.actions.insert(dir.clone(), action);
+    }
+
+    fn check_removal(
+        &self,
+        duplicate: &Duplicate,
+        dir: &str,
+        other: &str,
+        action: &Action,
+    ) -> Result<(), String> {
+        if self.policy.is_protected(dir) {
+            return Err(format!("{} is protected by the keep policy", dir));
+        }
+        let contained = match duplicate.relation {
+            Relation::Identical => true,
+            Relation::Subset => dir == duplicate.dir1,
+            Relation::Superset => dir == duplicate.dir2,
+            Relation::Overlap => false,
+        };
+        if *action == Action::Delete && !contained {
+            return Err(format!("{} has files which are not in {}", dir, other));
+        }
+        if is_inside(other, dir) {
+            return Err(format!("{} is inside {}", other, dir));
+        }
+        let marked = self
+            .actions
+            .iter()
+            .filter(|(marked, action)| marked.as_str() != dir && action.removes_dir());
+        for (marked, _) in marked {
+            if is_inside(other, marked) {
+                return Err(format!("{} is marked, one side must be kept", marked));
+            }
+            if is_inside(dir, marked) || is_inside(marked, dir) {
+                return Err(format!("{} is already marked with {}", dir, marked));
+            }
+            if self
+                .copies_in
+                .get(marked)
+                .is_some_and(|copies| is_inside(copies, dir))
+            {
+                return Err(format!("{} holds the copies of {}", dir, marked));
+            }
+        }
+        Ok(())
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        if let Some(details) = &mut self.details {
+            details.scroll = details.scroll.saturating_add_signed(offset as i16);
+            return;
+        }
+        if self.duplicates.is_empty() {
+            return;
+        }
+        let i = self.list_state.selected().unwrap_or(0) as isize + offset;
+        let i = i.clamp(0, self.duplicates.len() as isize - 1);
+        self.list_state.select(Some(i as usize));
+    }
+
+    /// Returns `Some` when the user is done, with the confirmed actions or with
+    /// no actions if the user quit.
+    fn handle_key(&mut self, code: KeyCode) -> Option<Vec<(String, Action)>> {
+        match &mut self.mode {
+            Mode::MoveTarget(target) => match code {
+                KeyCode::Char(c) => target.push(c),
+                KeyCode::Backspace => {
+                    target.pop();
+                }
+                KeyCode::Enter => {
+                    let target = PathBuf::from(target.trim());
+                    self.mode = Mode::Normal;
+                    if !target.as_os_str().is_empty() {
+                        self.mark(Action::Move(target));
+                    }
+                }
+                KeyCode::Esc => self.mode = Mode::Normal,
+                _ => {}
+            },
+            Mode::Confirm => match code {
+                KeyCode::Char('y') => {
+                    let plan = self
+                        .actions
+                        .iter()
+                        .filter(|(_, action)| action.removes_dir())
+                        .map(|(dir, action)| (dir.clone(), action.clone()))
+                        .collect();
+                    return Some(plan);
+                }
+                KeyCode::Char('n') | KeyCode::Esc => self.mode = Mode::Normal,
+                _ => {}
+            },
+            Mode::Normal => match code {
+                KeyCode::Char('q') => return Some(Vec::new()),
+                KeyCode::Esc if self.details.is_some() => self.details = None,
+                KeyCode::Esc => return Some(Vec::new()),
+                KeyCode::Up => self.move_selection(-1),
+                KeyCode::Down => self.move_selection(1),
+                KeyCode::PageUp => self.move_selection(-20),
+                KeyCode::PageDown => self.move_selection(20),
+                KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                    self.side = match self.side {
+                        Side::Dir1 => Side::Dir2,
+                        Side::Dir2 => Side::Dir1,
+                    }
+                }
+                KeyCode::Enter => self.details = self.selected().map(load_details),
+                KeyCode::Char('d') => self.mark(Action::Delete),
+                KeyCode::Char('m') => self.mode = Mode::MoveTarget(String::new()),
+                KeyCode::Char('k') => self.mark(Action::Keep),
+                KeyCode::Char('u') => {
+                    if let Some((dir, _)) = self.selected_dirs() {
+                        self.actions.remove(dir);
+                        self.copies_in.remove(dir);
+                        self.status = format!("unmarked {}", dir);
+                    }
+                }
+                KeyCode::Char('a') => {
+                    if self.actions.values().any(Action::removes_dir) {
+                        self.mode = Mode::Confirm;
+                    } else {
+                        self.status = String::from("Nothing to delete or move");
+                    }
+                }
+                _ => {}
+            },
+        }
+        None
+    }
+
+    fn dir_span(&self, dir: &'a str, highlight: bool) -> Vec<Span<'a>> {
+        let style = if highlight {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        let label = self.actions.get(dir).map(Action::label).unwrap_or("");
+        vec![Span::raw(label), Span::styled(dir, style)]
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        let selected = self.list_state.selected();
+        let items: Vec<ListItem> = self
+            .duplicates
+            .iter()
+            .enumerate()
+            .map(|(i, duplicate)| {
+                let is_selected = selected == Some(i);
+                let mut spans =
+                    self.dir_span(&duplicate.dir1, is_selected && self.side == Side::Dir1);
+                spans.push(Span::raw(format!(": {} - ", duplicate.dir1_files_number)));
+                spans
+                    .extend(self.dir_span(&duplicate.dir2, is_selected && self.side == Side::Dir2));
+                spans.push(Span::raw(format!(
+                    ": {} | {} | {}",
+                    duplicate.dir2_files_number, duplicate.intersection, duplicate.relation
+                )));
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Duplicates"))
+            .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, area, &mut self.list_state);
+    }
+
+    fn draw_details(&self, frame: &mut Frame, area: ratatui::layout::Rect, details: &Details) {
+        let duplicate = match self.selected() {
+            Some(duplicate) => duplicate,
+            None => return,
+        };
+        let columns = Layout::horizontal([
+            Constraint::Ratio(1, 3),
+            Constraint::Ratio(1, 3),
+            Constraint::Ratio(1, 3),
+        ])
+        .split(area);
+        let panels = [
+            (format!("Only in {}", duplicate.dir1), &details.only_in_dir1),
+            (String::from("Shared"), &details.shared),
+            (format!("Only in {}", duplicate.dir2), &details.only_in_dir2),
+        ];
+        for ((title, files), column) in panels.into_iter().zip(columns.iter()) {
+            let lines: Vec<Line> = files.iter().map(|f| Line::raw(f.as_str())).collect();
+            let paragraph = Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(format!(
+                    "{} ({})",
+                    title,
+                    files.len()
+                )))
+                .scroll((details.scroll, 0));
+            frame.render_widget(paragraph, *column);
+        }
+    }
+
+    fn draw_confirm(&self, frame: &mut Frame, area: ratatui::layout::Rect) {
+        let mut lines: Vec<Line> = Vec::new();
+        for (dir, action) in self.actions.iter() {
+            match action {
+                Action::Keep => {}
+                Action::Delete => lines.push(Line::raw(format!("delete {}", dir))),
+                Action::Move(target) => {
+                    lines.push(Line::raw(format!("move {} -> {}", dir, target.display())))
+                }
+            }
+        }
+        let paragraph = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Apply these actions?"),
+            )
+            .wrap(Wrap { trim: false });
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+
+        match self.mode {
+            Mode::Confirm => self.draw_confirm(frame, main),
+            _ => match &self.details {
+                Some(details) => self.draw_details(frame, main, details),
+                None => self.draw_list(frame, main),
+            },
+        }
+
+        let text = match &self.mode {
+            Mode::MoveTarget(target) => format!("Move to: {}", target),
+            Mode::Confirm => String::from("y apply  n back"),
+            Mode::Normal if self.status.is_empty() => String::from(HELP),
+            Mode::Normal => format!("{}  |  {}", self.status, HELP),
+        };
+        let paragraph = Paragraph::new(text).block(Block::default().borders(Borders::ALL));
+        frame.render_widget(paragraph, status);
+    }
+}
+
+fn run_app(
+    terminal: &mut DefaultTerminal,
+    duplicates: &[Duplicate],
+    policy: &Policy,
+) -> io::Result<Vec<(String, Action)>> {
+    let mut app = App {
+        duplicates,
+        policy,
+        list_state: ListState::default(),
+        side: Side::Dir1,
+        details: None,
+        mode: Mode::Normal,
+        actions: BTreeMap::new(),
+        copies_in: HashMap::new(),
+        status: String::new(),
+    };
+    if !duplicates.is_empty() {
+        app.list_state.select(Some(0));
+    }
+
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(plan) = app.handle_key(key.code) {
+                return Ok(plan);
+            }
+        }
+    }
+}
+
+/// Lets the user review `duplicates` and returns the confirmed actions.
+/// Directories protected by `policy` can't be marked for deletion or moving.
+pub fn run(duplicates: &[Duplicate], policy: &Policy) -> io::Result<Vec<(String, Action)>> {
+    let mut terminal = ratatui::try_init()?;
+    let result = run_app(&mut terminal, duplicates, policy);
+    ratatui::try_restore()?;
+    result
+}
+
+/// Where `dir` ends up when it is moved into `target`.
+fn destination(dir: &str, target: &Path) -> io::Result<PathBuf> {
+    let name = Path::new(dir)
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no directory name"))?;
+    let destination = target.join(name);
+    if destination.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", destination.display()),
+        ));
+    }
+    Ok(destination)
+}
+
+fn move_dir(dir: &str, destination: &Path) -> io::Result<()> {
+    if let Some(target) = destination.parent() {
+        fs::create_dir_all(target)?;
+    }
+    fs::rename(dir, destination)
+}
+
+/// Carries out `plan`. Files of directories marked for deletion are removed
+/// as `removal` tells only when they have verified copies which stay in
+/// place, see `actions::delete_dirs`, and deletions are done before moves.
+/// Nothing is moved in a dry run. Every deleted, moved or skipped directory
+/// and every one which can't be moved is reported to `observer`.
+pub fn apply(
+    plan: &[(String, Action)],
+    result: &ScanResult,
+    removal: Removal,
+    observer: &dyn Observer,
+) -> Summary {
+    let deleted: Vec<&str> = plan
+        .iter()
+        .filter(|(_, action)| *action == Action::Delete)
+        .map(|(dir, _)| dir.as_str())
+        .collect();
+    let summary = actions::delete_dirs(&deleted, result, removal, observer);
+
+    let dry_run = removal == Removal::DryRun;
+    for (dir, action) in plan.iter() {
+        if let Action::Move(target) = action {
+            let moved = destination(dir, target).and_then(|destination| {
+                if !dry_run {
+                    move_dir(dir, &destination)?;
+                }
+                Ok(destination)
+            });
+            match moved {
+                Ok(destination) => observer.event(&dirdups::Event::DirMoved {
+                    path: Path::new(dir),
+                    target: &destination,
+                    dry_run,
+                }),
+                Err(e) => observer.event(&dirdups::Event::FileError {
+                    error: &Error::from_io(Path::new(dir), e),
+                }),
+            }
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dirdups::policy::Rule;
+    use dirdups::Scanner;
+
+    fn duplicate(dir1: &str, dir2: &str, relation: Relation) -> Duplicate {
+        Duplicate {
+            dir1: String::from(dir1),
+            dir2: String::from(dir2),
+            dir1_files_number: 1,
+            dir2_files_number: 1,
+            intersection: 1,
+            dir1_size: 0,
+            dir2_size: 0,
+            shared_size: 0,
+            jaccard: 1.0,
+            containment: 1.0,
+            relation,
+            decision: None,
+            hardlinked: false,
+            shared_files: Vec::new(),
+        }
+    }
+
+    /// A policy for an empty scan of a new temporary directory.
+    fn policy(name: &str, rules: Vec<Rule>) -> Policy {
+        let dir = std::env::temp_dir().join(format!("dirdups-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let result = Scanner::new([dir.to_string_lossy()]).scan().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        Policy::new(rules, &result)
+    }
+
+    fn app<'a>(duplicates: &'a [Duplicate], policy: &'a Policy) -> App<'a> {
+        App {
+            duplicates,
+            policy,
+            list_state: ListState::default(),
+            side: Side::Dir1,
+            details: None,
+            mode: Mode::Normal,
+            actions: BTreeMap::new(),
+            copies_in: HashMap::new(),
+            status: String::new(),
+        }
+    }
+
+    /// Marks side `side` of pair `pair` and returns whether the mark was taken.
+    fn mark(app: &mut App, pair: usize, side: Side, action: Action) -> bool {
+        app.list_state.select(Some(pair));
+        app.side = side;
+        let (dir, _) = app.selected_dirs().unwrap();
+        app.mark(action);
+        app.actions.contains_key(dir)
+    }
+
+    #[test]
+    fn only_contained_sides_can_be_deleted() {
+        let duplicates = [
+            duplicate("/A", "/B", Relation::Overlap),
+            duplicate("/C", "/D", Relation::Subset),
+            duplicate("/E", "/F", Relation::Superset),
+        ];
+        let policy = policy("tui-contained", Vec::new());
+        let mut app = app(&duplicates, &policy);
+        assert!(!mark(&mut app, 0, Side::Dir1, Action::Delete));
+        assert!(mark(
+            &mut app,
+            0,
+            Side::Dir1,
+            Action::Move(PathBuf::from("/G"))
+        ));
+        assert!(!mark(&mut app, 1, Side::Dir2, Action::Delete));
+        assert!(mark(&mut app, 1, Side::Dir1, Action::Delete));
+        assert!(!mark(&mut app, 2, Side::Dir1, Action::Delete));
+        assert!(mark(&mut app, 2, Side::Dir2, Action::Delete));
+    }
+
+    #[test]
+    fn protected_dirs_cant_be_marked() {
+        let duplicates = [duplicate("/keep/A", "/B", Relation::Identical)];
+        let policy = policy("tui-protected", vec![Rule::Protect(String::from("/keep/"))]);
+        let mut app = app(&duplicates, &policy);
+        assert!(!mark(&mut app, 0, Side::Dir1, Action::Delete));
+        assert!(mark(&mut app, 0, Side::Dir1, Action::Keep));
+        assert!(mark(&mut app, 0, Side::Dir2, Action::Delete));
+    }
+
+    #[test]
+    fn marks_keep_a_copy_of_every_marked_dir() {
+        let duplicates = [
+            duplicate("/A", "/B", Relation::Identical),
+            duplicate("/B", "/C", Relation::Identical),
+            duplicate("/A/sub", "/D", Relation::Identical),
+            duplicate("/E", "/C/sub", Relation::Identical),
+            duplicate("/F", "/G/sub", Relation::Identical),
+            duplicate("/G", "/H", Relation::Identical),
+        ];
+        let policy = policy("tui-marks", Vec::new());
+        let mut app = app(&duplicates, &policy);
+        assert!(mark(&mut app, 0, Side::Dir1, Action::Delete));
+        // Both sides of a pair.
+        assert!(!mark(&mut app, 0, Side::Dir2, Action::Delete));
+        // B holds the copies of A.
+        assert!(!mark(&mut app, 1, Side::Dir1, Action::Delete));
+        // Inside a marked directory.
+        assert!(!mark(&mut app, 2, Side::Dir1, Action::Delete));
+        assert!(mark(&mut app, 1, Side::Dir2, Action::Delete));
+        // The copies of E are inside the marked C.
+        assert!(!mark(&mut app, 3, Side::Dir1, Action::Delete));
+        // G contains the copies of the marked F.
+        assert!(mark(&mut app, 4, Side::Dir1, Action::Delete));
+        assert!(!mark(&mut app, 5, Side::Dir1, Action::Delete));
+        assert!(mark(&mut app, 5, Side::Dir2, Action::Delete));
+    }
+}
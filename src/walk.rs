@@ -0,0 +1,267 @@
+use crate::error::Error;
+use crate::observer::{Event, Observer};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{DirEntry, WalkBuilder};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Per-tree ignore file, written in gitignore syntax.
+pub const IGNORE_FILENAME: &str = ".dirdupsignore";
+
+/// Which files and directories the walk skips.
+pub struct WalkOptions {
+    /// Files and directories matching these globs are skipped, directories
+    /// with all their contents.
+    pub exclude: Vec<String>,
+    /// If not empty, only files matching one of these globs are scanned.
+    /// Directories are always entered.
+    pub include: Vec<String>,
+    /// Honor `.gitignore` and `.ignore` files, also outside of git repositories,
+    /// as well as the global gitignore and `.git/info/exclude`. The `.gitignore`
+    /// and `.ignore` files themselves are then skipped too.
+    pub gitignore: bool,
+    pub skip_hidden: bool,
+    pub follow_symlinks: bool,
+    /// Yield symlinks themselves instead of skipping them.
+    pub symlinks_as_entries: bool,
+}
+
+fn glob_error(glob: &str, error: ignore::Error) -> Error {
+    let reason = match error {
+        ignore::Error::Glob { err, .. } => err,
+        error => error.to_string(),
+    };
+    Error::InvalidOption(format!("invalid glob: {}: {}", glob, reason))
+}
+
+/// Globs are matched relatively to `root`, like patterns of a `.gitignore` in it.
+/// Exclusions are added last, so they win over inclusions.
+fn build_overrides(root: &str, options: &WalkOptions) -> Result<Override, Error> {
+    let mut builder = OverrideBuilder::new(root);
+    for glob in options.include.iter() {
+        builder.add(glob).map_err(|e| glob_error(glob, e))?;
+    }
+    for glob in options.exclude.iter() {
+        builder
+            .add(&format!("!{}", glob))
+            .map_err(|e| glob_error(glob, e))?;
+    }
+    builder
+        .build()
+        .map_err(|e| Error::InvalidOption(format!("invalid glob: {}", e)))
+}
+
+/// The file or directory which caused a walk error, if the error tells it,
+/// and what happened to it.
+fn describe_error(error: &ignore::Error) -> (Option<&Path>, io::Error) {
+    match error {
+        ignore::Error::WithPath { path, err } => (Some(path), describe_error(err).1),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            describe_error(err)
+        }
+        // The kind tells a permission error or a vanished file apart.
+        ignore::Error::Io(err) => (None, io::Error::new(err.kind(), err.to_string())),
+        error => (None, io::Error::other(error.to_string())),
+    }
+}
+
+/// The symlink and the directory it leads back to, if the error is a symlink loop.
+fn symlink_loop(error: &ignore::Error) -> Option<(&Path, &Path)> {
+    match error {
+        ignore::Error::Loop { ancestor, child } => Some((child, ancestor)),
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => symlink_loop(err),
+        _ => None,
+    }
+}
+
+/// Path of `entry` with symlinks resolved. Directories are resolved once and
+/// cached in `real_dirs`, a symlink entry itself is resolved only when it is
+/// followed.
+fn real_path(
+    entry: &DirEntry,
+    follow_symlinks: bool,
+    real_dirs: &mut HashMap<PathBuf, PathBuf>,
+) -> io::Result<PathBuf> {
+    if follow_symlinks && entry.path_is_symlink() {
+        return fs::canonicalize(entry.path());
+    }
+    let dir = entry.path().parent().unwrap_or(Path::new("."));
+    let real_dir = match real_dirs.get(dir) {
+        Some(real_dir) => real_dir.clone(),
+        None => {
+            let real_dir = fs::canonicalize(dir)?;
+            real_dirs.insert(dir.to_path_buf(), real_dir.clone());
+            real_dir
+        }
+    };
+    Ok(match entry.path().file_name() {
+        Some(name) => real_dir.join(name),
+        None => real_dir,
+    })
+}
+
+/// A file found by the walk.
+pub struct FoundFile {
+    /// The path the file was found by.
+    pub path: String,
+    /// The path with symlinks resolved, the same whatever path the file was
+    /// found by and whatever the working directory is.
+    pub real_path: String,
+}
+
+/// Files of all `directories`. Excluded directories are pruned during the walk,
+/// so nothing below them is read. A file reachable by several paths, through
+/// symlinks or overlapping `directories`, is returned only once, so it is
+/// never reported as a duplicate of itself. Fails when one of `directories`
+/// can't be read, e.g. an unmounted drive, which must not look like a
+/// directory without duplicates.
+pub fn get_files(
+    directories: &[String],
+    options: &WalkOptions,
+    observer: &dyn Observer,
+) -> Result<Vec<FoundFile>, Error> {
+    let mut files: Vec<FoundFile> = Vec::new();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut real_dirs: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+    for directory in directories.iter() {
+        fs::read_dir(directory).map_err(|e| Error::from_io(Path::new(directory), e))?;
+        let overrides = build_overrides(directory, options)?;
+        let walk = WalkBuilder::new(directory)
+            .standard_filters(false)
+            .hidden(options.skip_hidden)
+            .ignore(options.gitignore)
+            .git_ignore(options.gitignore)
+            .git_global(options.gitignore)
+            .git_exclude(options.gitignore)
+            .parents(options.gitignore)
+            .require_git(false)
+            .follow_links(options.follow_symlinks)
+            .add_custom_ignore_filename(IGNORE_FILENAME)
+            .overrides(overrides)
+            .build();
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    if let Some((path, ancestor)) = symlink_loop(&e) {
+                        observer.event(&Event::SymlinkLoop { path, ancestor });
+                        continue;
+                    }
+                    let (path, error) = describe_error(&e);
+                    let path = path.unwrap_or(Path::new(directory));
+                    observer.event(&Event::FileError {
+                        error: &Error::from_io(path, error),
+                    });
+                    continue;
+                }
+            };
+            let wanted = entry
+                .file_type()
+                .is_some_and(|t| t.is_file() || (options.symlinks_as_entries && t.is_symlink()));
+            // Ignore files are settings of the walk, not contents to compare.
+            let name = entry.file_name();
+            if !wanted
+                || name == IGNORE_FILENAME
+                || (options.gitignore && (name == ".gitignore" || name == ".ignore"))
+            {
+                continue;
+            }
+            let real_path = match real_path(&entry, options.follow_symlinks, &mut real_dirs) {
+                Ok(real_path) => real_path,
+                Err(e) => {
+                    observer.event(&Event::FileError {
+                        error: &Error::from_io(entry.path(), e),
+                    });
+                    continue;
+                }
+            };
+            if seen.contains(&real_path) {
+                continue;
+            }
+            files.push(FoundFile {
+                path: String::from(entry.path().to_string_lossy()),
+                real_path: String::from(real_path.to_string_lossy()),
+            });
+            seen.insert(real_path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::Silent;
+
+    fn options() -> WalkOptions {
+        WalkOptions {
+            exclude: Vec::new(),
+            include: Vec::new(),
+            gitignore: false,
+            skip_hidden: false,
+            follow_symlinks: false,
+            symlinks_as_entries: false,
+        }
+    }
+
+    /// Names of files found in a new temporary directory holding `files`.
+    fn found(name: &str, files: &[(&str, &str)], options: &WalkOptions) -> Vec<String> {
+        let root = std::env::temp_dir().join(format!("dirdups-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (file, contents) in files.iter() {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let directories = [String::from(root.to_string_lossy())];
+        let mut names: Vec<String> = get_files(&directories, options, &Silent)
+            .unwrap()
+            .iter()
+            .map(|file| {
+                let path = Path::new(&file.path).strip_prefix(&root).unwrap();
+                String::from(path.to_string_lossy())
+            })
+            .collect();
+        names.sort();
+        fs::remove_dir_all(root).unwrap();
+        names
+    }
+
+    #[test]
+    fn ignore_files_are_applied_but_not_scanned() {
+        let files = [
+            ("a/.dirdupsignore", "*.tmp\n"),
+            ("a/x", "x"),
+            ("a/y.tmp", "y"),
+        ];
+        assert_eq!(found("walk-ignore", &files, &options()), ["a/x"]);
+    }
+
+    #[test]
+    fn gitignore_files_are_skipped_only_with_gitignore() {
+        let files = [
+            ("a/.gitignore", "*.tmp\n"),
+            ("a/.ignore", "y\n"),
+            ("a/x", "x"),
+            ("a/y", "y"),
+        ];
+        let gitignore = WalkOptions {
+            gitignore: true,
+            ..options()
+        };
+        assert_eq!(found("walk-gitignore", &files, &gitignore), ["a/x"]);
+        let all = found("walk-no-gitignore", &files, &options());
+        assert_eq!(all, ["a/.gitignore", "a/.ignore", "a/x", "a/y"]);
+    }
+
+    #[test]
+    fn missing_directories_fail_the_walk() {
+        let directories = [String::from("/nonexistent/dirdups")];
+        assert!(get_files(&directories, &options(), &Silent).is_err());
+    }
+}